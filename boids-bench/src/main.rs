@@ -0,0 +1,180 @@
+//! Steps every flock implementation in one process, from the same initial flock, and prints one
+//! table of step times. No window is opened, so the numbers cover the simulation alone. The SIMD
+//! row is the `portable_simd` flock on a nightly toolchain and its scalar fallback otherwise.
+
+use std::time::{Duration, Instant};
+
+use boids_common::embed::{initial_boids, FlockParams};
+use boids_common::*;
+use boids_rs::config::ScalarOptions;
+use boids_rs::frame_loop::Simulation;
+use boids_rs::{default_impl, multithreaded_impl};
+use boids_simd_rs::config::SimdOptions;
+use glam::Vec2;
+
+type Config = boids_common::Config<BenchOptions>;
+
+/// The options of both binaries, each going to the implementations it has, plus the run length.
+#[derive(Clone)]
+struct BenchOptions {
+    steps: u32,
+    warmup_steps: u32,
+    scalar: ScalarOptions,
+    simd: SimdOptions,
+}
+
+impl Default for BenchOptions {
+    fn default() -> Self {
+        BenchOptions {
+            steps: 100,
+            warmup_steps: 10,
+            scalar: ScalarOptions::default(),
+            simd: SimdOptions::default(),
+        }
+    }
+}
+
+impl Options for BenchOptions {
+    const NUM_BOIDS: u16 = 2000;
+
+    fn parse_arg(&mut self, arg: &str, args: &mut dyn Iterator<Item = String>) -> bool {
+        match arg {
+            "--steps" => {
+                if let Some(steps) = next_value::<u32>(args, arg) {
+                    self.steps = steps.max(1);
+                }
+            }
+            "--warmup-steps" => {
+                if let Some(steps) = next_value(args, arg) {
+                    self.warmup_steps = steps;
+                }
+            }
+            _ => return self.scalar.parse_arg(arg, args) || self.simd.parse_arg(arg, args),
+        }
+        true
+    }
+}
+
+/// The step times of one implementation.
+struct Row {
+    name: &'static str,
+    times: Vec<Duration>,
+}
+
+impl Row {
+    fn measure(name: &'static str, config: &Config, mut step: impl FnMut()) -> Self {
+        for _ in 0..config.options.warmup_steps {
+            step();
+        }
+        let mut times: Vec<Duration> = (0..config.options.steps)
+            .map(|_| {
+                let start = Instant::now();
+                step();
+                start.elapsed()
+            })
+            .collect();
+        times.sort_unstable();
+        Row { name, times }
+    }
+
+    fn median(&self) -> Duration {
+        self.times[self.times.len() / 2]
+    }
+
+    fn mean(&self) -> Duration {
+        self.times.iter().sum::<Duration>() / self.times.len() as u32
+    }
+}
+
+fn main() {
+    perf_instrument::start();
+
+    let config = Config::from_args();
+    if config.thread_priority {
+        configure_threads(true, 0);
+    }
+    report_extra_work(config.extra_work);
+
+    // The window size of both binaries
+    let rect_max = Vec2::new(1080.0, 800.0);
+    let dt = 1.0 / 60.0;
+    let boids = initial_boids(&FlockParams {
+        num_boids: usize::from(config.num_boids),
+        bounds: rect_max,
+        spawn: config.spawn,
+        seed: config.seed,
+        threaded: true,
+    });
+    let packed: Vec<_> = boids
+        .iter()
+        .map(|boid| (boid.position, boid.velocity))
+        .collect();
+    let scalar_config = boids_rs::config::Config {
+        num_boids: config.num_boids,
+        seed: config.seed,
+        spawn: config.spawn,
+        extra_work: config.extra_work,
+        options: config.options.scalar.clone(),
+        ..Default::default()
+    };
+
+    let mut rows = vec![];
+    let mut sim = default_impl::Flock::from_boids(&scalar_config, rect_max, &packed);
+    rows.push(Row::measure(sim.name(), &config, || {
+        sim.step(1, dt, to_real(rect_max), &[], 0.0);
+    }));
+    let mut sim = multithreaded_impl::Flock::from_boids(&scalar_config, &packed);
+    rows.push(Row::measure(sim.name(), &config, || {
+        sim.step(1, dt, to_real(rect_max), &[], 0.0);
+    }));
+    let mut flock = boids_simd_rs::Flock::new(&boids, config.extra_work, &config.options.simd);
+    rows.push(Row::measure(boids_simd_rs::PATH_NAME, &config, || {
+        flock.step(dt, rect_max);
+    }));
+
+    println!(
+        "{} boids, {:?} spawn, seed {}, {} steps after {} warmup steps",
+        config.num_boids,
+        config.spawn,
+        config.seed,
+        config.options.steps,
+        config.options.warmup_steps
+    );
+    println!(
+        "SIMD path: {}",
+        boids_simd_rs::path_description(&config.options.simd)
+    );
+    println!(
+        "{:<16} {:>10} {:>10} {:>10} {:>8}",
+        "implementation", "median ms", "mean ms", "min ms", "speedup"
+    );
+    let baseline = rows[0].median();
+    for row in &rows {
+        println!(
+            "{:<16} {:>10.3} {:>10.3} {:>10.3} {:>7.1}x",
+            row.name,
+            row.median().as_secs_f64() * 1e3,
+            row.mean().as_secs_f64() * 1e3,
+            row.times[0].as_secs_f64() * 1e3,
+            baseline.as_secs_f64() / row.median().as_secs_f64()
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn each_binary_gets_its_own_options() {
+        let config = Config::parse(
+            ["--steps", "0", "--par-chunks", "64", "--unroll", "2", "500"]
+                .iter()
+                .map(|arg| arg.to_string()),
+        );
+        assert_eq!(config.options.steps, 1);
+        assert_eq!(config.options.scalar.par_chunks, Some(64));
+        assert_eq!(config.options.simd.unroll, 2);
+        assert_eq!(config.num_boids, 500);
+    }
+}
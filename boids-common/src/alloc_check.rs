@@ -0,0 +1,92 @@
+//! Counts heap allocations through a wrapper around the system allocator and panics when a
+//! steady-state frame allocates inside an `alloc_free!` block. Only the parts this repo owns are
+//! marked, the simulation step, the snapshot and the instance upload: ggez's canvas and the
+//! overlay text allocate every frame by design. A change in the boid count or implementation
+//! starts the warm-up over, since the buffers are expected to grow then.
+//!
+//! Rayon takes work from outside the pool through a queue that allocates a block every 63 jobs,
+//! so `alloc_free!(pool ...)` blocks don't count the main thread while it waits on the workers.
+//! The workers themselves are counted as usual.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+const WARMUP_FRAMES: u64 = 10;
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+static STEADY_FRAMES: AtomicU64 = AtomicU64::new(0);
+static NUM_BOIDS: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    static WAITING_ON_POOL: Cell<bool> = const { Cell::new(false) };
+}
+
+fn count() {
+    if !WAITING_ON_POOL.try_with(Cell::get).unwrap_or(false) {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+pub struct CountingAlloc;
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        count();
+        System.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        count();
+        System.alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        count();
+        System.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+pub fn allocations() -> u64 {
+    ALLOCATIONS.load(Ordering::Relaxed)
+}
+
+/// Called at the start of every update.
+pub fn frame(num_boids: usize) {
+    if NUM_BOIDS.swap(num_boids, Ordering::Relaxed) == num_boids {
+        STEADY_FRAMES.fetch_add(1, Ordering::Relaxed);
+    } else {
+        restart();
+    }
+}
+
+pub fn restart() {
+    STEADY_FRAMES.store(0, Ordering::Relaxed);
+}
+
+/// Stops counting this thread's allocations until dropped.
+pub struct PoolWait(());
+
+impl PoolWait {
+    pub fn start() -> Self {
+        WAITING_ON_POOL.set(true);
+        PoolWait(())
+    }
+}
+
+impl Drop for PoolWait {
+    fn drop(&mut self) {
+        WAITING_ON_POOL.set(false);
+    }
+}
+
+pub fn check(label: &str, before: u64) {
+    let allocated = allocations() - before;
+    if allocated > 0 && STEADY_FRAMES.load(Ordering::Relaxed) >= WARMUP_FRAMES {
+        panic!("{label} allocated {allocated} times in a steady-state frame");
+    }
+}
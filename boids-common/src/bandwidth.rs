@@ -0,0 +1,80 @@
+//! Roofline-style check of the update kernels: each implementation states how many bytes one
+//! step moves from its data sizes, and the achieved rate is compared against a STREAM-style triad
+//! run once at startup. A flock that fits in cache goes past 100%, which is the point where the
+//! layout stops being about DRAM and starts being about how many cache lines each visit pulls in.
+
+use std::time::{Duration, Instant};
+
+use rayon::prelude::*;
+
+const CACHE_LINE: usize = 64;
+// 64 MiB per array, well past any last level cache
+const TRIAD_LEN: usize = 1 << 23;
+const TRIAD_RUNS: usize = 5;
+
+/// Bytes one visit to an element of `size` costs when only its first cache line is hot.
+pub const fn hot_line_bytes(size: usize) -> usize {
+    if size < CACHE_LINE {
+        size
+    } else {
+        CACHE_LINE
+    }
+}
+
+/// Best of a few `a = b + s * c` passes, in bytes per second. Runs on the rayon pool, a
+/// single core can't saturate the memory controller.
+pub fn measure_peak() -> f64 {
+    let mut a = vec![0.0f64; TRIAD_LEN];
+    let b = vec![1.0f64; TRIAD_LEN];
+    let c = vec![2.0f64; TRIAD_LEN];
+    let mut best = Duration::MAX;
+    for _ in 0..TRIAD_RUNS {
+        let start = Instant::now();
+        a.par_iter_mut()
+            .zip(&b)
+            .zip(&c)
+            .for_each(|((a, b), c)| *a = b + 3.0 * c);
+        best = best.min(start.elapsed());
+        std::hint::black_box(&mut a);
+    }
+    (3 * TRIAD_LEN * std::mem::size_of::<f64>()) as f64 / best.as_secs_f64()
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Stats {
+    pub peak: f64,
+    pub achieved: f64,
+}
+
+impl Stats {
+    pub fn measure() -> Stats {
+        Stats {
+            peak: measure_peak(),
+            achieved: 0.0,
+        }
+    }
+
+    /// One step over `num_boids`: every boid reads `read_per_pair` bytes of every other boid
+    /// and writes `written_per_boid` bytes of its own next state.
+    pub fn take_frame(
+        &mut self,
+        num_boids: usize,
+        read_per_pair: usize,
+        written_per_boid: usize,
+        elapsed: Duration,
+    ) {
+        let bytes = num_boids * num_boids * read_per_pair + num_boids * written_per_boid;
+        if !elapsed.is_zero() {
+            self.achieved = bytes as f64 / elapsed.as_secs_f64();
+        }
+    }
+
+    pub fn text(&self) -> String {
+        format!(
+            "Bandwidth: {:.1} GB/s, {:.0}% of {:.1} GB/s triad",
+            self.achieved / 1e9,
+            self.achieved / self.peak * 100.0,
+            self.peak / 1e9
+        )
+    }
+}
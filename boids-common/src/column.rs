@@ -0,0 +1,123 @@
+//! Fixed-length `f32` columns for the structure-of-arrays boids, which `--huge-pages` puts on
+//! transparent huge pages.
+
+use std::alloc::{self, Layout};
+use std::ops::{Deref, DerefMut};
+use std::ptr::NonNull;
+
+/// The transparent huge page size of x86-64 and of the common aarch64 kernels.
+pub const HUGE_PAGE_SIZE: usize = 2 << 20;
+
+/// A zeroed `[f32]` of fixed length.
+///
+/// Without huge pages it comes from `calloc`, so its pages are only faulted in by whoever writes
+/// them first. With huge pages the allocation is aligned and padded out to whole huge pages and
+/// advised with `MADV_HUGEPAGE` before it is zeroed, so the kernel faults it in 2 MiB at a time
+/// and a column of up to 512k boids takes a single dTLB entry.
+pub struct Column {
+    ptr: NonNull<f32>,
+    len: usize,
+    layout: Layout,
+}
+
+// Owns its buffer like a `Vec<f32>`
+unsafe impl Send for Column {}
+unsafe impl Sync for Column {}
+
+impl Column {
+    pub fn zeroed(len: usize, huge_pages: bool) -> Self {
+        let layout = if huge_pages {
+            let size = (len * size_of::<f32>())
+                .next_multiple_of(HUGE_PAGE_SIZE)
+                .max(HUGE_PAGE_SIZE);
+            Layout::from_size_align(size, HUGE_PAGE_SIZE)
+        } else {
+            Layout::array::<f32>(len)
+        }
+        .expect("column larger than the address space");
+        if layout.size() == 0 {
+            return Column {
+                ptr: NonNull::dangling(),
+                len,
+                layout,
+            };
+        }
+
+        // SAFETY: the layout is not zero-sized
+        let raw = unsafe {
+            if huge_pages {
+                alloc::alloc(layout)
+            } else {
+                alloc::alloc_zeroed(layout)
+            }
+        };
+        let Some(ptr) = NonNull::new(raw.cast::<f32>()) else {
+            alloc::handle_alloc_error(layout);
+        };
+        if huge_pages {
+            advise_huge_pages(raw, layout.size());
+            // SAFETY: the allocation holds at least `len` floats, and zero bits are 0.0
+            unsafe { ptr.as_ptr().write_bytes(0, len) };
+        }
+        Column { ptr, len, layout }
+    }
+}
+
+impl Deref for Column {
+    type Target = [f32];
+
+    fn deref(&self) -> &[f32] {
+        // SAFETY: `len` floats were zeroed in `zeroed` and the allocation lives as long as `self`
+        unsafe { std::slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl DerefMut for Column {
+    fn deref_mut(&mut self) -> &mut [f32] {
+        // SAFETY: as in `deref`, with `&mut self` making the access unique
+        unsafe { std::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl Drop for Column {
+    fn drop(&mut self) {
+        if self.layout.size() != 0 {
+            // SAFETY: allocated in `zeroed` with this layout
+            unsafe { alloc::dealloc(self.ptr.as_ptr().cast(), self.layout) };
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn advise_huge_pages(ptr: *mut u8, size: usize) {
+    // SAFETY: the range is one whole allocation of ours, the advice only changes its backing
+    if unsafe { libc::madvise(ptr.cast(), size, libc::MADV_HUGEPAGE) } != 0 {
+        eprintln!(
+            "madvise(MADV_HUGEPAGE) failed, the column stays on regular pages: {}",
+            std::io::Error::last_os_error()
+        );
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn advise_huge_pages(_ptr: *mut u8, _size: usize) {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn columns_start_zeroed_and_huge_ones_fill_whole_pages() {
+        for huge_pages in [false, true] {
+            let mut column = Column::zeroed(1000, huge_pages);
+            assert_eq!(column.len(), 1000);
+            assert!(column.iter().all(|&value| value == 0.0));
+            column[999] = 1.0;
+            assert_eq!(column.iter().sum::<f32>(), 1.0);
+        }
+        let column = Column::zeroed(1000, true);
+        assert_eq!(column.as_ptr() as usize % HUGE_PAGE_SIZE, 0);
+        assert_eq!(column.layout.size(), HUGE_PAGE_SIZE);
+        assert!(Column::zeroed(0, true).is_empty());
+    }
+}
@@ -0,0 +1,222 @@
+use std::env;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use crate::{Palette, Spawn};
+
+/// The command line options only one binary has, parsed from whatever the shared ones leave.
+pub trait Options: Default {
+    /// Boids spawned when the command line doesn't give a count.
+    const NUM_BOIDS: u16 = 4000;
+
+    /// Takes `arg`, and its value from `args`, if it is one of these options.
+    fn parse_arg(&mut self, arg: &str, args: &mut dyn Iterator<Item = String>) -> bool;
+}
+
+impl Options for () {
+    fn parse_arg(&mut self, _arg: &str, _args: &mut dyn Iterator<Item = String>) -> bool {
+        false
+    }
+}
+
+/// Command line options, shared by every implementation. `options` holds the ones of a single
+/// binary.
+#[derive(Clone)]
+pub struct Config<O = ()> {
+    pub num_boids: u16,
+    pub vsync: bool,
+    /// Update/draw rate while the window is unfocused or minimized, 0 disables throttling.
+    pub idle_fps: f32,
+    pub palette: Palette,
+    pub seed: u64,
+    pub spawn: Spawn,
+    pub thread_priority: bool,
+    /// First-touch the simulation buffers from the threads that update them and size the draw
+    /// buffers before the first frame.
+    pub prefault: bool,
+    pub page_faults: bool,
+    /// Read the RAPL energy counters and report joules per simulated frame.
+    pub energy: bool,
+    pub checksum: bool,
+    pub checksum_log: bool,
+    /// Minutes of frame times kept for the dump hotkey, 0 disables the history.
+    pub history_minutes: f32,
+    /// Capture frames slower than this many times the recent median, 0 disables the watch.
+    pub hitch_factor: f32,
+    /// Where to write the per-second speed histograms on exit, see `SpeedHistogram`.
+    pub speed_histogram: Option<PathBuf>,
+    /// Simulation steps per rendered frame, each advancing by an equal share of the frame time.
+    pub sim_steps_per_frame: u32,
+    /// Clamp simulation steps to `MAX_STABLE_DT` instead of only warning about them.
+    pub clamp_dt: bool,
+    /// Rounds of synthetic per-boid work added to every step, see `extra_work`.
+    pub extra_work: u32,
+    pub options: O,
+}
+
+impl<O: Options> Default for Config<O> {
+    fn default() -> Self {
+        Config {
+            num_boids: O::NUM_BOIDS,
+            vsync: false,
+            idle_fps: 4.0,
+            palette: Palette::DEFAULT,
+            seed: 0,
+            spawn: Spawn::Uniform,
+            thread_priority: false,
+            prefault: false,
+            page_faults: false,
+            energy: false,
+            checksum: false,
+            checksum_log: false,
+            history_minutes: 5.0,
+            hitch_factor: 0.0,
+            speed_histogram: None,
+            sim_steps_per_frame: 1,
+            clamp_dt: false,
+            extra_work: 0,
+            options: O::default(),
+        }
+    }
+}
+
+impl<O: Options> Config<O> {
+    pub fn from_args() -> Self {
+        Self::parse(env::args().skip(1))
+    }
+
+    pub fn parse(mut args: impl Iterator<Item = String>) -> Self {
+        let mut config = Self::default();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--vsync" => config.vsync = true,
+                "--thread-priority" => config.thread_priority = true,
+                "--prefault" => config.prefault = true,
+                "--page-faults" => config.page_faults = true,
+                "--energy" => config.energy = true,
+                "--clamp-dt" => config.clamp_dt = true,
+                "--checksum" => config.checksum = true,
+                "--checksum-log" => {
+                    config.checksum = true;
+                    config.checksum_log = true;
+                }
+                "--history-minutes" => {
+                    if let Some(history_minutes) = next_value(&mut args, &arg) {
+                        config.history_minutes = history_minutes;
+                    }
+                }
+                "--hitch-factor" => {
+                    if let Some(factor) = next_value(&mut args, &arg) {
+                        config.hitch_factor = factor;
+                    }
+                }
+                "--speed-histogram" => {
+                    if let Some(path) = next_value(&mut args, &arg) {
+                        config.speed_histogram = Some(path);
+                    }
+                }
+                "--sim-steps-per-frame" => {
+                    if let Some(steps) = next_value::<u32>(&mut args, &arg) {
+                        config.sim_steps_per_frame = steps.max(1);
+                    }
+                }
+                "--extra-work" => {
+                    if let Some(rounds) = next_value(&mut args, &arg) {
+                        config.extra_work = rounds;
+                    }
+                }
+                "--idle-fps" => {
+                    if let Some(idle_fps) = next_value(&mut args, &arg) {
+                        config.idle_fps = idle_fps;
+                    }
+                }
+                "--palette" => match args.next().as_deref().and_then(Palette::from_name) {
+                    Some(palette) => config.palette = palette,
+                    None => eprintln!(
+                        "Unknown palette for `{arg}`, expected default, high-contrast or colorblind"
+                    ),
+                },
+                "--seed" => {
+                    if let Some(seed) = next_value(&mut args, &arg) {
+                        config.seed = seed;
+                    }
+                }
+                "--spawn" => match args.next().as_deref().and_then(Spawn::from_name) {
+                    Some(spawn) => config.spawn = spawn,
+                    None => eprintln!(
+                        "Unknown spawn for `{arg}`, expected uniform, cluster, ring or grid"
+                    ),
+                },
+                _ if config.options.parse_arg(&arg, &mut args) => {}
+                _ => match arg.parse::<u16>() {
+                    Ok(num_boids) => config.num_boids = num_boids,
+                    Err(_) => eprintln!("Ignoring unknown argument `{arg}`"),
+                },
+            }
+        }
+        config
+    }
+}
+
+/// Parses the value after `flag`, warning when it is missing or doesn't parse.
+pub fn next_value<T: FromStr>(
+    args: &mut (impl Iterator<Item = String> + ?Sized),
+    flag: &str,
+) -> Option<T> {
+    let value = args.next().and_then(|value| value.parse().ok());
+    if value.is_none() {
+        eprintln!("Missing or invalid value for `{flag}`, using the default");
+    }
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct Extra {
+        level: Option<u32>,
+    }
+
+    impl Options for Extra {
+        const NUM_BOIDS: u16 = 10;
+
+        fn parse_arg(&mut self, arg: &str, args: &mut dyn Iterator<Item = String>) -> bool {
+            match arg {
+                "--level" => self.level = next_value(args, arg),
+                _ => return false,
+            }
+            true
+        }
+    }
+
+    fn parse<O: Options>(args: &[&str]) -> Config<O> {
+        Config::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn shared_flags_values_and_the_boid_count_parse() {
+        let config: Config = parse(&["--vsync", "--seed", "7", "--spawn", "ring", "2500"]);
+        assert!(config.vsync);
+        assert_eq!(config.seed, 7);
+        assert_eq!(config.spawn, Spawn::Ring);
+        assert_eq!(config.num_boids, 2500);
+        assert_eq!(config.sim_steps_per_frame, 1);
+
+        let config: Config = parse(&["--sim-steps-per-frame", "0", "--checksum-log", "--level"]);
+        assert_eq!(config.sim_steps_per_frame, 1);
+        assert!(config.checksum && config.checksum_log);
+        assert_eq!(config.num_boids, 4000);
+    }
+
+    #[test]
+    fn binary_options_get_the_arguments_the_shared_ones_leave() {
+        let config: Config<Extra> =
+            parse(&["--level", "3", "--energy", "--speed-histogram", "out.json"]);
+        assert_eq!(config.options.level, Some(3));
+        assert!(config.energy);
+        assert_eq!(config.speed_histogram, Some(PathBuf::from("out.json")));
+        assert_eq!(config.num_boids, 10);
+    }
+}
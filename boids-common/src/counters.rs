@@ -0,0 +1,249 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+/// Minor and major page faults of the whole process so far.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct PageFaults {
+    pub minor: u64,
+    pub major: u64,
+}
+
+#[cfg(target_os = "linux")]
+pub fn page_faults() -> Option<PageFaults> {
+    let stat = std::fs::read_to_string("/proc/self/stat").ok()?;
+    // The command name in parentheses may contain spaces, the numbered fields start after it
+    let mut fields = stat.rsplit_once(')')?.1.split_whitespace();
+    let minor = fields.nth(7)?.parse().ok()?;
+    let major = fields.nth(1)?.parse().ok()?;
+    Some(PageFaults { minor, major })
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn page_faults() -> Option<PageFaults> {
+    None
+}
+
+/// KiB of the process's anonymous memory on transparent huge pages.
+#[cfg(target_os = "linux")]
+pub fn anon_huge_pages_kib() -> Option<u64> {
+    let rollup = std::fs::read_to_string("/proc/self/smaps_rollup").ok()?;
+    let line = rollup
+        .lines()
+        .find_map(|line| line.strip_prefix("AnonHugePages:"))?;
+    line.trim().strip_suffix("kB")?.trim().parse().ok()
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn anon_huge_pages_kib() -> Option<u64> {
+    None
+}
+
+/// `--huge-pages` prints how much of the process ended up on huge pages, which stays at 0 when
+/// transparent huge pages are disabled or the kernel found no free 2 MiB frames.
+pub fn report_huge_pages(phase: &str) {
+    match anon_huge_pages_kib() {
+        Some(kib) => eprintln!("Anonymous memory on huge pages after {phase}: {kib} KiB"),
+        None => eprintln!("Huge page counts are only available on Linux"),
+    }
+}
+
+/// `--page-faults` prints the faults taken by each startup phase and by the first frames, which
+/// is where a first-frame spike usually comes from.
+pub struct FaultReport {
+    last: PageFaults,
+    frames: u32,
+}
+
+impl FaultReport {
+    pub fn start() -> Option<FaultReport> {
+        let report = page_faults().map(|last| FaultReport { last, frames: 0 });
+        if report.is_none() {
+            eprintln!("Page fault counts are only available on Linux");
+        }
+        report
+    }
+
+    pub fn phase(&mut self, name: &str) {
+        if let Some(now) = page_faults() {
+            eprintln!(
+                "Page faults during {name}: {} minor, {} major",
+                now.minor - self.last.minor,
+                now.major - self.last.major
+            );
+            self.last = now;
+        }
+    }
+
+    /// Call once per drawn frame.
+    pub fn frame_done(&mut self) {
+        self.frames += 1;
+        match self.frames {
+            1 => self.phase("the first frame"),
+            100 => self.phase("frames 2-100"),
+            _ => {}
+        }
+    }
+}
+
+const POWERCAP_DIR: &str = "/sys/class/powercap";
+
+/// Microjoules a RAPL counter advanced by, across at most one wrap at `range_uj`.
+pub fn counter_delta(last_uj: u64, now_uj: u64, range_uj: u64) -> u64 {
+    if now_uj >= last_uj {
+        now_uj - last_uj
+    } else {
+        range_uj - last_uj + now_uj
+    }
+}
+
+struct RaplZone {
+    energy_path: PathBuf,
+    range_uj: u64,
+    last_uj: u64,
+}
+
+fn read_u64(path: &Path) -> io::Result<u64> {
+    std::fs::read_to_string(path)?
+        .trim()
+        .parse()
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+/// `--energy`: package energy from the Linux RAPL powercap counters, summed over the sockets.
+/// Reported per simulated frame rather than per drawn one, so the number stays comparable when
+/// `--sim-steps-per-frame` changes.
+pub struct EnergyMeter {
+    zones: Vec<RaplZone>,
+    last_read: Instant,
+    start: Instant,
+    watts: f64,
+    total_joules: f64,
+    simulated_frames: u64,
+}
+
+impl EnergyMeter {
+    pub fn open() -> Option<EnergyMeter> {
+        // Top-level zones are the packages, `intel-rapl:0:0` and deeper are parts of them
+        let zones: io::Result<Vec<RaplZone>> =
+            std::fs::read_dir(POWERCAP_DIR).and_then(|entries| {
+                entries
+                    .filter_map(Result::ok)
+                    .map(|entry| entry.path())
+                    .filter(|path| {
+                        path.file_name()
+                            .and_then(|name| name.to_str())
+                            .is_some_and(|name| name.matches(':').count() == 1)
+                    })
+                    .map(|path| {
+                        let energy_path = path.join("energy_uj");
+                        Ok(RaplZone {
+                            last_uj: read_u64(&energy_path)?,
+                            range_uj: read_u64(&path.join("max_energy_range_uj"))?,
+                            energy_path,
+                        })
+                    })
+                    .collect()
+            });
+        match zones {
+            Ok(zones) if !zones.is_empty() => {
+                let now = Instant::now();
+                Some(EnergyMeter {
+                    zones,
+                    last_read: now,
+                    start: now,
+                    watts: 0.0,
+                    total_joules: 0.0,
+                    simulated_frames: 0,
+                })
+            }
+            Ok(_) => {
+                eprintln!("No RAPL package counters under {POWERCAP_DIR}");
+                None
+            }
+            Err(err) => {
+                eprintln!("Could not read the RAPL counters under {POWERCAP_DIR}: {err} (energy_uj is root-only on most kernels)");
+                None
+            }
+        }
+    }
+
+    /// Call once per drawn frame with the simulation steps it ran, 0 while paused.
+    pub fn frame_done(&mut self, sim_steps: u32) {
+        let mut microjoules = 0;
+        for zone in &mut self.zones {
+            if let Ok(now_uj) = read_u64(&zone.energy_path) {
+                microjoules += counter_delta(zone.last_uj, now_uj, zone.range_uj);
+                zone.last_uj = now_uj;
+            }
+        }
+        let joules = microjoules as f64 * 1e-6;
+        let elapsed = self.last_read.elapsed().as_secs_f64();
+        self.last_read = Instant::now();
+        if elapsed > 0.0 {
+            self.watts = joules / elapsed;
+        }
+        self.total_joules += joules;
+        self.simulated_frames += u64::from(sim_steps);
+    }
+
+    fn joules_per_frame(&self) -> f64 {
+        self.total_joules / self.simulated_frames.max(1) as f64
+    }
+
+    pub fn text(&self) -> String {
+        format!(
+            "Energy: {:.1} W, {:.2} mJ per simulated frame",
+            self.watts,
+            self.joules_per_frame() * 1e3
+        )
+    }
+
+    /// Starts the totals over for the next implementation's part of the run, on the counters
+    /// that are already open.
+    pub fn restart(&mut self) {
+        self.start = Instant::now();
+        self.total_joules = 0.0;
+        self.simulated_frames = 0;
+    }
+
+    /// Totals since the meter was opened or restarted, printed when the run or the
+    /// implementation ends.
+    pub fn report(&self, label: &str) {
+        let seconds = self.start.elapsed().as_secs_f64();
+        eprintln!(
+            "Energy, {label}: {:.1} J over {:.1} s ({:.1} W), {} simulated frames, {:.2} mJ per simulated frame",
+            self.total_joules,
+            seconds,
+            self.total_joules / seconds,
+            self.simulated_frames,
+            self.joules_per_frame() * 1e3
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rapl_counter_delta_survives_a_wrap() {
+        assert_eq!(counter_delta(1_000, 4_000, 10_000), 3_000);
+        assert_eq!(counter_delta(9_000, 2_000, 10_000), 3_000);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn page_faults_grow_when_touching_new_memory() {
+        let before = page_faults().unwrap();
+        let pages = vec![1u8; 64 << 20];
+        std::hint::black_box(&pages);
+        assert!(page_faults().unwrap().minor > before.minor);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn huge_page_usage_is_read_from_the_rollup() {
+        assert!(anon_huge_pages_kib().is_some());
+    }
+}
@@ -0,0 +1,46 @@
+//! Per-frame counts of the numeric edge cases in the rules: neighbors dropped by the epsilon
+//! guard, rules that found no neighbors at all, and forces cut down by the clamp. Counting goes
+//! to a thread-local first and is flushed once per boid, so worker threads don't fight over the
+//! shared counters in the inner loops.
+
+use std::cell::Cell;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Counts {
+    pub epsilon_guard: u32,
+    pub zero_neighbors: u32,
+    pub clamped: u32,
+}
+
+thread_local! {
+    static LOCAL_COUNTS: Cell<Counts> = Cell::new(Counts::default());
+}
+
+static EPSILON_GUARD: AtomicU32 = AtomicU32::new(0);
+static ZERO_NEIGHBORS: AtomicU32 = AtomicU32::new(0);
+static CLAMPED: AtomicU32 = AtomicU32::new(0);
+
+#[inline(always)]
+pub fn add(count: impl FnOnce(&mut Counts)) {
+    LOCAL_COUNTS.with(|local| {
+        let mut counts = local.get();
+        count(&mut counts);
+        local.set(counts);
+    });
+}
+
+pub fn flush() {
+    let counts = LOCAL_COUNTS.take();
+    EPSILON_GUARD.fetch_add(counts.epsilon_guard, Ordering::Relaxed);
+    ZERO_NEIGHBORS.fetch_add(counts.zero_neighbors, Ordering::Relaxed);
+    CLAMPED.fetch_add(counts.clamped, Ordering::Relaxed);
+}
+
+pub fn take_frame() -> Counts {
+    Counts {
+        epsilon_guard: EPSILON_GUARD.swap(0, Ordering::Relaxed),
+        zero_neighbors: ZERO_NEIGHBORS.swap(0, Ordering::Relaxed),
+        clamped: CLAMPED.swap(0, Ordering::Relaxed),
+    }
+}
@@ -0,0 +1,109 @@
+//! Per-frame histogram of the distances between boids and the neighbors the rules take into
+//! account, bucketed over the larger of the two radii. Recorded from the separation loop, which
+//! measures every pair anyway, with the same thread-local-then-flush scheme as the diagnostics.
+
+use std::cell::Cell;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use ggez::graphics::{self, Canvas, DrawParam, Text};
+use glam::Vec2;
+
+use crate::{to_render, Palette, Real, RealVec2, PERCEPTION, SEPARATION};
+
+pub const BUCKETS: usize = 16;
+const RANGE: Real = if PERCEPTION > SEPARATION {
+    PERCEPTION
+} else {
+    SEPARATION
+};
+const PANEL_WIDTH: f32 = 240.0;
+const PANEL_HEIGHT: f32 = 40.0;
+
+pub type Buckets = [u32; BUCKETS];
+
+thread_local! {
+    static LOCAL_BUCKETS: Cell<Buckets> = const { Cell::new([0; BUCKETS]) };
+}
+
+static BUCKET_COUNTS: [AtomicU32; BUCKETS] = [const { AtomicU32::new(0) }; BUCKETS];
+
+#[inline(always)]
+fn bucket(distance: Real) -> Option<usize> {
+    (distance > 0.0 && distance < RANGE)
+        .then(|| ((distance / RANGE * BUCKETS as Real) as usize).min(BUCKETS - 1))
+}
+
+#[inline(always)]
+pub fn record(distance: Real) {
+    if let Some(bucket) = bucket(distance) {
+        LOCAL_BUCKETS.with(|local| {
+            let mut buckets = local.get();
+            buckets[bucket] += 1;
+            local.set(buckets);
+        });
+    }
+}
+
+/// `record` for a batch, such as the lanes of a SIMD distance, with one thread-local access.
+#[inline(always)]
+pub fn record_all(distances: impl IntoIterator<Item = Real>) {
+    LOCAL_BUCKETS.with(|local| {
+        let mut buckets = local.get();
+        for bucket in distances.into_iter().filter_map(bucket) {
+            buckets[bucket] += 1;
+        }
+        local.set(buckets);
+    });
+}
+
+pub fn flush() {
+    let buckets = LOCAL_BUCKETS.take();
+    for (total, count) in BUCKET_COUNTS.iter().zip(buckets) {
+        total.fetch_add(count, Ordering::Relaxed);
+    }
+}
+
+pub fn take_frame() -> Buckets {
+    BUCKET_COUNTS
+        .each_ref()
+        .map(|total| total.swap(0, Ordering::Relaxed))
+}
+
+/// Bars scaled to the fullest bucket, with a tick under each rule radius.
+pub fn draw(canvas: &mut Canvas, dest: Vec2, buckets: &Buckets, palette: &Palette) {
+    canvas.draw(
+        &Text::new(format!(
+            "Neighbor distances 0-{RANGE}, separation {SEPARATION}, perception {PERCEPTION}"
+        )),
+        DrawParam::new().dest(dest).color(palette.text),
+    );
+    let top = dest.y + 12.0;
+    let fullest = buckets.iter().copied().max().unwrap_or(0).max(1) as f32;
+    let bar_width = PANEL_WIDTH / BUCKETS as f32;
+    for (bucket, &count) in buckets.iter().enumerate() {
+        let height = count as f32 / fullest * PANEL_HEIGHT;
+        canvas.draw(
+            &graphics::Quad,
+            DrawParam::new()
+                .dest_rect(graphics::Rect::new(
+                    dest.x + bucket as f32 * bar_width,
+                    top + PANEL_HEIGHT - height,
+                    bar_width - 1.0,
+                    height,
+                ))
+                .color(palette.boid),
+        );
+    }
+    for (radius, color) in [
+        (SEPARATION, palette.attracted_boid),
+        (PERCEPTION, palette.text),
+    ] {
+        let x = dest.x + to_render(RealVec2::new(radius / RANGE, 0.0)).x * PANEL_WIDTH;
+        canvas.draw(
+            &graphics::Quad,
+            DrawParam::new()
+                .dest_rect(graphics::Rect::new(x - 1.0, top + PANEL_HEIGHT, 2.0, 6.0))
+                .color(color),
+        );
+    }
+}
@@ -0,0 +1,163 @@
+//! The flock without a window, for engines that bring their own renderer: build a `Flock` from
+//! `FlockParams`, call `step(dt)` once per tick and read `positions()`. Depending on this crate
+//! with `default-features = false` leaves out the `app` feature and with it ggez and tracy.
+
+use glam::Vec2;
+use rand::Rng;
+use rayon::prelude::*;
+
+use crate::scalar::Boid;
+use crate::{seeded_rng, to_real, Real, RealVec2, Spawn, MAX_SPEED};
+
+#[derive(Debug, Clone, Copy)]
+pub struct FlockParams {
+    pub num_boids: usize,
+    /// Boids spawn in and wrap around the edges of the rectangle from the origin to `bounds`.
+    pub bounds: Vec2,
+    pub spawn: Spawn,
+    pub seed: u64,
+    /// Steps the boids on rayon's global pool instead of on the calling thread.
+    pub threaded: bool,
+}
+
+impl Default for FlockParams {
+    fn default() -> Self {
+        FlockParams {
+            num_boids: 4000,
+            bounds: Vec2::new(1920.0, 1080.0),
+            spawn: Spawn::Uniform,
+            seed: 0,
+            threaded: true,
+        }
+    }
+}
+
+/// The flock of `params`, placed by `params.spawn` and heading in random directions at half of
+/// `MAX_SPEED`, like the binaries start theirs.
+pub fn initial_boids(params: &FlockParams) -> Vec<Boid> {
+    let mut rng = seeded_rng(params.seed);
+    (0..params.num_boids)
+        .map(|boid_idx| {
+            let position =
+                params
+                    .spawn
+                    .position(boid_idx, params.num_boids, params.bounds, &mut rng);
+            // Randomized in f32 either way, so the f32 and f64 builds start from the same flock
+            let angle = rng.gen_range(0.0..std::f32::consts::TAU);
+            Boid::new(
+                to_real(position),
+                to_real(Vec2::from_angle(angle)) * MAX_SPEED / 2.0,
+            )
+        })
+        .collect()
+}
+
+/// The scalar rules of `scalar::Boid`, double buffered so a step allocates nothing.
+pub struct Flock {
+    current: Vec<Boid>,
+    next: Vec<Boid>,
+    positions: Vec<RealVec2>,
+    bounds: RealVec2,
+    threaded: bool,
+}
+
+impl Flock {
+    pub fn new(params: &FlockParams) -> Self {
+        Self::from_boids(initial_boids(params), params.bounds, params.threaded)
+    }
+
+    pub fn from_boids(boids: Vec<Boid>, bounds: Vec2, threaded: bool) -> Self {
+        Flock {
+            positions: boids.iter().map(|boid| boid.position).collect(),
+            next: Vec::with_capacity(boids.len()),
+            current: boids,
+            bounds: to_real(bounds),
+            threaded,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.current.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.current.is_empty()
+    }
+
+    /// One position per boid, in spawn order, as of the last step.
+    pub fn positions(&self) -> &[RealVec2] {
+        &self.positions
+    }
+
+    pub fn boids(&self) -> &[Boid] {
+        &self.current
+    }
+
+    /// Moves every boid on by `dt` seconds. Steps longer than `MAX_STABLE_DT` make the flock
+    /// oscillate, see `checked_dt`.
+    pub fn step(&mut self, dt: Real) {
+        let (current_boids, bounds) = (&self.current, self.bounds);
+        let stepped = |boid_idx: usize| {
+            let boid = &current_boids[boid_idx];
+            let acceleration = boid.calc_acceleration(boid_idx, current_boids, &[], 0.0);
+            let mut next_boid = Boid::default();
+            next_boid.update(dt, boid, acceleration);
+            next_boid.edges(bounds.x, bounds.y);
+            next_boid
+        };
+        self.next.clear();
+        if self.threaded {
+            self.next.par_extend(
+                (0..current_boids.len())
+                    .into_par_iter()
+                    .with_min_len(64)
+                    .map(stepped),
+            );
+        } else {
+            self.next.extend((0..current_boids.len()).map(stepped));
+        }
+        std::mem::swap(&mut self.current, &mut self.next);
+
+        for (position, boid) in self.positions.iter_mut().zip(&self.current) {
+            *position = boid.position;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn threaded_and_serial_steps_agree_and_stay_in_bounds() {
+        let params = FlockParams {
+            num_boids: 300,
+            bounds: Vec2::new(400.0, 300.0),
+            spawn: Spawn::Cluster,
+            seed: 3,
+            threaded: false,
+        };
+        let mut serial = Flock::new(&params);
+        let mut threaded = Flock::new(&FlockParams {
+            threaded: true,
+            ..params
+        });
+        assert_eq!(serial.positions(), threaded.positions());
+        #[cfg(not(feature = "static_update"))]
+        let start = serial.positions().to_vec();
+        for _ in 0..20 {
+            serial.step(0.05);
+            threaded.step(0.05);
+        }
+        assert_eq!(serial.len(), 300);
+        assert_eq!(serial.positions(), threaded.positions());
+        // `static_update` keeps every boid where it spawned
+        #[cfg(not(feature = "static_update"))]
+        assert_ne!(serial.positions(), &start[..]);
+        let bounds = to_real(params.bounds);
+        for (position, boid) in serial.positions().iter().zip(serial.boids()) {
+            assert_eq!(*position, boid.position);
+            assert!(position.cmpge(RealVec2::ZERO).all() && position.cmple(bounds).all());
+        }
+    }
+}
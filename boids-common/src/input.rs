@@ -0,0 +1,135 @@
+use std::fmt::Write as _;
+
+use ggez::event::Button;
+use ggez::input::keyboard::KeyCode;
+use ggez::Context;
+use perf_instrument::tracy_message;
+
+pub const HELP_WIDTH: f32 = 260.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    ToggleHelp,
+    ToggleAttraction,
+    TogglePause,
+    ToggleVsync,
+    AddBoids,
+    RemoveBoids,
+    ToggleParChunks,
+    DumpHistory,
+    SwitchImplementation,
+    ToggleTracking,
+}
+
+pub struct Binding {
+    pub action: Action,
+    pub key: Option<KeyCode>,
+    pub button: Option<Button>,
+    pub description: &'static str,
+}
+
+/// The one list of controls. Keyboard and gamepad handling look actions up here and the F1 help
+/// overlay is generated from it, so a new control only has to be added in this place.
+pub const BINDINGS: &[Binding] = &[
+    Binding {
+        action: Action::ToggleHelp,
+        key: Some(KeyCode::F1),
+        button: None,
+        description: "show/hide this help",
+    },
+    Binding {
+        action: Action::ToggleAttraction,
+        key: Some(KeyCode::A),
+        button: Some(Button::South),
+        description: "attract to the cursor",
+    },
+    Binding {
+        action: Action::TogglePause,
+        key: Some(KeyCode::Space),
+        button: Some(Button::Start),
+        description: "pause",
+    },
+    Binding {
+        action: Action::ToggleVsync,
+        key: Some(KeyCode::V),
+        button: Some(Button::West),
+        description: "vsync",
+    },
+    Binding {
+        action: Action::AddBoids,
+        key: Some(KeyCode::Up),
+        button: Some(Button::DPadUp),
+        description: "add boids",
+    },
+    Binding {
+        action: Action::RemoveBoids,
+        key: Some(KeyCode::Down),
+        button: Some(Button::DPadDown),
+        description: "remove boids",
+    },
+    Binding {
+        action: Action::ToggleParChunks,
+        key: Some(KeyCode::C),
+        button: None,
+        description: "scheduling",
+    },
+    Binding {
+        action: Action::DumpHistory,
+        key: Some(KeyCode::D),
+        button: None,
+        description: "dump frame history",
+    },
+    Binding {
+        action: Action::SwitchImplementation,
+        key: Some(KeyCode::I),
+        button: None,
+        description: "implementation",
+    },
+    Binding {
+        action: Action::ToggleTracking,
+        key: Some(KeyCode::T),
+        button: None,
+        description: "track the drag selection",
+    },
+];
+
+pub fn pressed_action(ctx: &Context) -> Option<Action> {
+    BINDINGS
+        .iter()
+        .find(|binding| {
+            binding
+                .key
+                .is_some_and(|key| ctx.keyboard.is_key_just_pressed(key))
+        })
+        .map(|binding| binding.action)
+}
+
+/// Marks a used control on the profiler timeline, with the state it left behind, so a capture
+/// shows what changed on screen.
+pub fn report_action(action: Action, state: Option<String>) {
+    match state {
+        Some(state) => tracy_message!("{action:?}: {state}"),
+        None => tracy_message!("{action:?}"),
+    }
+}
+
+/// One line per binding of the `supported` actions, with the current state of the mode it
+/// toggles where `state` reports one.
+pub fn help_text(
+    supported: impl Fn(Action) -> bool,
+    state: impl Fn(Action) -> Option<String>,
+) -> String {
+    let mut text = String::new();
+    for binding in BINDINGS.iter().filter(|binding| supported(binding.action)) {
+        let key = binding.key.map_or(String::new(), |key| format!("{key:?}"));
+        let button = binding
+            .button
+            .map_or(String::new(), |button| format!("{button:?}"));
+        let _ = write!(text, "{key:<6} {button:<9} {}", binding.description);
+        if let Some(state) = state(binding.action) {
+            let _ = write!(text, ": {state}");
+        }
+        text.push('\n');
+    }
+    text
+}
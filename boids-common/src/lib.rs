@@ -0,0 +1,479 @@
+//! Everything around the flock update that the boids binaries share: the rule constants and
+//! scalar helpers, the overlay instruments and their macros, the frame monitors, the energy and
+//! page fault counters, the command line options, the control bindings, palettes, the render
+//! snapshot, boid mesh and overlay, and spawn distributions. Each instrument is behind a feature
+//! of the same name, which the binaries forward from their own features. Everything that needs
+//! ggez or tracy is behind the default `app` feature, `embed` is the flock without either.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+#[cfg(feature = "app")]
+use std::time::Duration;
+use std::time::Instant;
+
+#[cfg(feature = "app")]
+use ggez::Context;
+use glam::Vec2;
+#[cfg(feature = "app")]
+use perf_instrument::tracy_scope;
+use thread_priority::{set_current_thread_priority, ThreadPriority};
+
+#[cfg(feature = "alloc_check")]
+pub mod alloc_check;
+#[cfg(feature = "bandwidth")]
+pub mod bandwidth;
+mod column;
+#[cfg(feature = "app")]
+mod config;
+mod counters;
+#[cfg(feature = "diagnostics")]
+pub mod diagnostics;
+#[cfg(feature = "distance_histogram")]
+pub mod distance_histogram;
+pub mod embed;
+#[cfg(feature = "app")]
+mod input;
+#[cfg(feature = "app")]
+mod monitor;
+#[cfg(feature = "app")]
+mod palette;
+#[cfg(feature = "app")]
+mod render;
+#[cfg(feature = "rule_timing")]
+pub mod rule_timing;
+// The rule loops index the boid slices on purpose so that the self-skip reads the same everywhere.
+#[allow(clippy::needless_range_loop)]
+pub mod scalar;
+mod spawn;
+#[cfg(any(test, feature = "test-support"))]
+pub mod test_support;
+
+pub use column::*;
+#[cfg(feature = "app")]
+pub use config::*;
+pub use counters::*;
+#[cfg(feature = "app")]
+pub use input::*;
+#[cfg(feature = "app")]
+pub use monitor::*;
+#[cfg(feature = "app")]
+pub use palette::*;
+#[cfg(feature = "app")]
+pub use render::*;
+pub use spawn::*;
+
+// The `f64` feature runs the simulation in double precision, as a reference to bound the drift of
+// the f32 paths against and to measure what the extra precision costs. Rendering stays in f32.
+#[cfg(not(feature = "f64"))]
+pub type Real = f32;
+#[cfg(not(feature = "f64"))]
+pub type RealVec2 = glam::Vec2;
+#[cfg(feature = "f64")]
+pub type Real = f64;
+#[cfg(feature = "f64")]
+pub type RealVec2 = glam::DVec2;
+
+pub const BOID_SIZE: f32 = 10.0;
+pub const MAX_SPEED: Real = 100.0;
+pub const MAX_FORCE: Real = 80.0;
+pub const PERCEPTION: Real = 100.0;
+pub const SEPARATION: Real = 100.0;
+
+// The rule constants can't be tuned at runtime, so the combinations that leave a degenerate flock
+// are rejected at compile time: separating beyond the perception radius pushes boids apart before
+// they align or group, and a zero speed or force limit never steers at all.
+const _: () = assert!(
+    SEPARATION <= PERCEPTION,
+    "SEPARATION must not exceed PERCEPTION"
+);
+const _: () = assert!(
+    MAX_SPEED > 0.0 && MAX_FORCE > 0.0,
+    "the limits must be positive"
+);
+
+/// Past this step length one step of `MAX_FORCE` can turn a boid around at full speed, and the
+/// flock starts to oscillate.
+pub const MAX_STABLE_DT: Real = MAX_SPEED / MAX_FORCE;
+
+/// Warns the first time a simulation step is longer than `MAX_STABLE_DT`, which a stalled window
+/// usually causes, and clamps it with `--clamp-dt`.
+pub fn checked_dt(dt: Real, clamp: bool) -> Real {
+    static WARNED: AtomicBool = AtomicBool::new(false);
+    if dt <= MAX_STABLE_DT {
+        return dt;
+    }
+    if !WARNED.swap(true, Ordering::Relaxed) {
+        eprintln!(
+            "A {dt:.3} s step lets MAX_FORCE * dt exceed MAX_SPEED, {}",
+            if clamp {
+                "clamping steps like it"
+            } else {
+                "pass --clamp-dt to clamp them"
+            }
+        );
+    }
+    if clamp {
+        MAX_STABLE_DT
+    } else {
+        dt
+    }
+}
+
+// Hot functions are `inline(never)` by default so each rule shows up on its own in the profiler.
+// `inline_default` leaves the decision to the compiler and `inline_always` forces inlining, which
+// turns the profiling-clarity cost into a number that can be read off the frame time.
+#[cfg(not(feature = "inline_default"))]
+pub const INLINING: &str = "never";
+#[cfg(all(feature = "inline_default", not(feature = "inline_always")))]
+pub const INLINING: &str = "default";
+#[cfg(feature = "inline_always")]
+pub const INLINING: &str = "always";
+
+/// 1.0 if `condition` holds and 0.0 otherwise, the scalar stand-in for a SIMD lane mask.
+#[cfg(feature = "branchless")]
+#[inline(always)]
+pub fn mask_weight(condition: bool) -> Real {
+    Real::from(u8::from(condition))
+}
+
+#[cfg(not(feature = "f64"))]
+pub fn to_real(v: Vec2) -> RealVec2 {
+    v
+}
+
+#[cfg(not(feature = "f64"))]
+pub fn to_render(v: RealVec2) -> Vec2 {
+    v
+}
+
+#[cfg(feature = "f64")]
+pub fn to_real(v: Vec2) -> RealVec2 {
+    v.as_dvec2()
+}
+
+#[cfg(feature = "f64")]
+pub fn to_render(v: RealVec2) -> Vec2 {
+    v.as_vec2()
+}
+
+/// atan2 from an octant reduction and a degree 7 polynomial, within 2e-4 rad of the real thing,
+/// which is far below what a boid mesh can show.
+pub fn fast_atan2(y: f32, x: f32) -> f32 {
+    let (abs_x, abs_y) = (x.abs(), y.abs());
+    let max = abs_x.max(abs_y);
+    if max == 0.0 {
+        return 0.0;
+    }
+    let a = abs_x.min(abs_y) / max;
+    let s = a * a;
+    let mut angle = ((-0.046_496_474 * s + 0.159_314_22) * s - 0.327_622_76) * s * a + a;
+    if abs_y > abs_x {
+        angle = std::f32::consts::FRAC_PI_2 - angle;
+    }
+    if x < 0.0 {
+        angle = std::f32::consts::PI - angle;
+    }
+    if y < 0.0 {
+        -angle
+    } else {
+        angle
+    }
+}
+
+/// Mesh rotation for a boid heading along `velocity`.
+#[cfg(not(feature = "fast_atan2"))]
+pub fn rotation_angle(velocity: Vec2) -> f32 {
+    velocity.y.atan2(velocity.x)
+}
+
+#[cfg(feature = "fast_atan2")]
+pub fn rotation_angle(velocity: Vec2) -> f32 {
+    fast_atan2(velocity.y, velocity.x)
+}
+
+/// `--extra-work`: `rounds` of an integer hash finalizer per boid and step. Pure ALU work that
+/// touches no memory, so raising it moves a step from memory bound towards compute bound. The
+/// seed goes through `black_box` so the rounds can't be hoisted out of the boid loop.
+#[inline(always)]
+pub fn extra_work(seed: usize, rounds: u32) {
+    let mut hash = std::hint::black_box(seed as u32);
+    for _ in 0..rounds {
+        hash ^= hash >> 16;
+        hash = hash.wrapping_mul(0x7feb_352d);
+        hash ^= hash >> 15;
+        hash = hash.wrapping_mul(0x846c_a68b);
+    }
+    std::hint::black_box(hash);
+}
+
+/// Times `extra_work` once at startup so the option can be read as a cost per boid.
+pub fn report_extra_work(rounds: u32) {
+    const SAMPLES: usize = 1000;
+    if rounds == 0 {
+        return;
+    }
+    let start = Instant::now();
+    for seed in 0..SAMPLES {
+        extra_work(seed, rounds);
+    }
+    let per_boid = start.elapsed() / SAMPLES as u32;
+    eprintln!(
+        "Extra work: {rounds} hash rounds, {:.2} us per boid and step",
+        per_boid.as_secs_f64() * 1e6
+    );
+}
+
+// The macros below are picked here by this crate's features, like the `perf-instrument` ones, so
+// a build without an instrument carries none of its code at the call sites.
+
+/// Adds `$count` to the `$counter` edge case of the current frame, see `diagnostics`.
+#[cfg(feature = "diagnostics")]
+#[macro_export]
+macro_rules! count_diagnostic {
+    ($counter:ident, $count:expr) => {
+        $crate::diagnostics::add(|counts| counts.$counter += ($count) as u32);
+    };
+}
+
+#[cfg(not(feature = "diagnostics"))]
+#[macro_export]
+macro_rules! count_diagnostic {
+    ($counter:ident, $count:expr) => {};
+}
+
+#[cfg(feature = "diagnostics")]
+#[macro_export]
+macro_rules! flush_diagnostics {
+    () => {
+        $crate::diagnostics::flush();
+    };
+}
+
+#[cfg(not(feature = "diagnostics"))]
+#[macro_export]
+macro_rules! flush_diagnostics {
+    () => {};
+}
+
+/// Evaluates `$force` and charges the time it took to `$rule`, see `rule_timing`.
+#[cfg(feature = "rule_timing")]
+#[macro_export]
+macro_rules! timed_rule {
+    ($rule:ident, $force:expr) => {{
+        let start = std::time::Instant::now();
+        let force = $force;
+        $crate::rule_timing::add(|times| times.$rule += start.elapsed().as_nanos() as u64);
+        force
+    }};
+}
+
+#[cfg(not(feature = "rule_timing"))]
+#[macro_export]
+macro_rules! timed_rule {
+    ($rule:ident, $force:expr) => {
+        $force
+    };
+}
+
+#[cfg(feature = "rule_timing")]
+#[macro_export]
+macro_rules! flush_rule_timing {
+    () => {
+        $crate::rule_timing::flush();
+    };
+}
+
+#[cfg(not(feature = "rule_timing"))]
+#[macro_export]
+macro_rules! flush_rule_timing {
+    () => {};
+}
+
+/// Evaluates `$body` and panics if it allocated in a steady-state frame, see `alloc_check`.
+#[cfg(feature = "alloc_check")]
+#[macro_export]
+macro_rules! alloc_free {
+    (pool $label:literal, $body:expr) => {{
+        let before = $crate::alloc_check::allocations();
+        let pool_wait = $crate::alloc_check::PoolWait::start();
+        let result = $body;
+        drop(pool_wait);
+        $crate::alloc_check::check($label, before);
+        result
+    }};
+    ($label:literal, $body:expr) => {{
+        let before = $crate::alloc_check::allocations();
+        let result = $body;
+        $crate::alloc_check::check($label, before);
+        result
+    }};
+}
+
+#[cfg(not(feature = "alloc_check"))]
+#[macro_export]
+macro_rules! alloc_free {
+    (pool $label:literal, $body:expr) => {
+        $body
+    };
+    ($label:literal, $body:expr) => {
+        $body
+    };
+}
+
+/// Counts one neighbor distance, or every distance of an iterator with `record_distances!`, see
+/// `distance_histogram`.
+#[cfg(feature = "distance_histogram")]
+#[macro_export]
+macro_rules! record_distance {
+    ($distance:expr) => {
+        $crate::distance_histogram::record($distance);
+    };
+}
+
+#[cfg(not(feature = "distance_histogram"))]
+#[macro_export]
+macro_rules! record_distance {
+    ($distance:expr) => {};
+}
+
+#[cfg(feature = "distance_histogram")]
+#[macro_export]
+macro_rules! record_distances {
+    ($distances:expr) => {
+        $crate::distance_histogram::record_all($distances);
+    };
+}
+
+#[cfg(not(feature = "distance_histogram"))]
+#[macro_export]
+macro_rules! record_distances {
+    ($distances:expr) => {};
+}
+
+#[cfg(feature = "distance_histogram")]
+#[macro_export]
+macro_rules! flush_distance_histogram {
+    () => {
+        $crate::distance_histogram::flush();
+    };
+}
+
+#[cfg(not(feature = "distance_histogram"))]
+#[macro_export]
+macro_rules! flush_distance_histogram {
+    () => {};
+}
+
+// ggez only reads `WindowSetup::vsync` when the window is created and re-applies that initial
+// surface configuration on every resize, so the present mode is switched by reconfiguring the
+// surface directly. Call it again from `resize_event` to keep the choice.
+#[cfg(feature = "app")]
+pub fn set_vsync(ctx: &Context, vsync: bool) {
+    let (width, height) = ctx.gfx.drawable_size();
+    let surface_config = wgpu::SurfaceConfiguration {
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+        format: ctx.gfx.surface_format(),
+        width: (width as u32).max(1),
+        height: (height as u32).max(1),
+        present_mode: if vsync {
+            wgpu::PresentMode::AutoVsync
+        } else {
+            wgpu::PresentMode::AutoNoVsync
+        },
+        alpha_mode: wgpu::CompositeAlphaMode::Auto,
+        view_formats: vec![],
+    };
+    let wgpu = ctx.gfx.wgpu();
+    wgpu.surface.configure(&wgpu.device, &surface_config);
+}
+
+/// `--thread-priority`: raises the main thread, which updates and renders, and lowers the rayon
+/// workers. On machines with few cores this stops the scheduler from parking the render thread
+/// behind simulation workers, which is where the odd long frame comes from. Raising usually
+/// needs elevated rights, lowering always works. `num_threads` sizes the global pool, 0 keeps
+/// rayon's default. Must run before rayon's global pool is first used.
+pub fn configure_threads(thread_priority: bool, num_threads: usize) {
+    if thread_priority {
+        if let Err(err) = set_current_thread_priority(ThreadPriority::Max) {
+            eprintln!("Could not raise the main thread priority: {err:?}");
+        }
+    }
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(num_threads)
+        .start_handler(move |_| {
+            if !thread_priority {
+                return;
+            }
+            if let Err(err) = set_current_thread_priority(ThreadPriority::Min) {
+                eprintln!("Could not lower a worker thread priority: {err:?}");
+            }
+        })
+        .build_global();
+    if let Err(err) = pool {
+        eprintln!("Could not configure the rayon pool: {err}");
+    }
+}
+
+/// Drops the update/draw rate to `idle_fps` while the window is unfocused or minimized, so a demo
+/// left running behind other slides doesn't heat the machine up before the next measurement.
+#[cfg(feature = "app")]
+pub struct IdleThrottle {
+    idle_fps: f32,
+    focused: bool,
+    minimized: bool,
+}
+
+#[cfg(feature = "app")]
+impl IdleThrottle {
+    pub fn new(idle_fps: f32) -> Self {
+        IdleThrottle {
+            idle_fps,
+            focused: true,
+            minimized: false,
+        }
+    }
+
+    pub fn set_focused(&mut self, focused: bool) {
+        self.focused = focused;
+    }
+
+    pub fn set_window_size(&mut self, width: f32, height: f32) {
+        self.minimized = width == 0.0 || height == 0.0;
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.idle_fps > 0.0 && (!self.focused || self.minimized)
+    }
+
+    pub fn wait(&self) {
+        if self.is_idle() {
+            tracy_scope!("idle");
+            std::thread::sleep(Duration::from_secs_f32(1.0 / self.idle_fps));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checked_dt_clamps_only_long_steps_and_only_when_asked() {
+        assert_eq!(checked_dt(0.016, true), 0.016);
+        assert_eq!(checked_dt(2.0, false), 2.0);
+        assert_eq!(checked_dt(2.0, true), MAX_STABLE_DT);
+    }
+
+    #[test]
+    fn fast_atan2_matches_atan2() {
+        for step in 0..3600 {
+            let direction = Vec2::from_angle(step as f32 * std::f32::consts::TAU / 3600.0);
+            for v in [direction, direction * 250.0, direction * 1e-3] {
+                assert!(
+                    (fast_atan2(v.y, v.x) - v.y.atan2(v.x)).abs() < 2.5e-4,
+                    "at {v}"
+                );
+            }
+        }
+        assert_eq!(fast_atan2(0.0, 0.0), 0.0);
+    }
+}
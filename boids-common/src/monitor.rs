@@ -0,0 +1,392 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use perf_instrument::tracy_message;
+
+use crate::{Real, RealVec2, MAX_SPEED};
+
+/// `--checksum` shows an FNV-1a hash of every boid's position and velocity bits next to the
+/// boid count, `--checksum-log` also prints `frame<TAB>checksum` to stdout after every
+/// simulation step. Two runs of the same build and seed must produce identical logs, so diffing
+/// them finds the first frame where they diverge.
+pub struct StateChecksum {
+    log: bool,
+    frame: u64,
+    pub value: u64,
+}
+
+impl StateChecksum {
+    pub fn new(log: bool) -> Self {
+        StateChecksum {
+            log,
+            frame: 0,
+            value: 0,
+        }
+    }
+
+    pub fn update(&mut self, values: impl IntoIterator<Item = Real>) {
+        const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+        const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+        #[cfg(not(feature = "f64"))]
+        let bits = |value: Real| u64::from(value.to_bits());
+        #[cfg(feature = "f64")]
+        let bits = |value: Real| value.to_bits();
+        self.value = values.into_iter().fold(FNV_OFFSET, |hash, value| {
+            (hash ^ bits(value)).wrapping_mul(FNV_PRIME)
+        });
+        if self.log {
+            println!("{}\t{:016x}", self.frame, self.value);
+        }
+        self.frame += 1;
+    }
+}
+
+/// The last `--history-minutes` of frame times, kept in memory so a hiccup noticed mid-demo can
+/// still be dumped with D after the fact. Samples age out by time rather than count, so an
+/// uncapped frame rate keeps the whole window too.
+pub struct FrameHistory {
+    start: Instant,
+    window: Duration,
+    samples: VecDeque<FrameSample>,
+}
+
+struct FrameSample {
+    at: Duration,
+    frame_time: Duration,
+    boids: usize,
+}
+
+impl FrameHistory {
+    pub fn new(window: Duration) -> Self {
+        FrameHistory {
+            start: Instant::now(),
+            window,
+            samples: VecDeque::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    pub fn record(&mut self, frame_time: Duration, boids: usize) {
+        let at = self.start.elapsed();
+        while self
+            .samples
+            .front()
+            .is_some_and(|sample| at - sample.at > self.window)
+        {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(FrameSample {
+            at,
+            frame_time,
+            boids,
+        });
+    }
+
+    /// Writes the samples as CSV to `boids-history-<unix time>.csv` in the working directory.
+    pub fn dump(&self) {
+        let unix_time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let path = format!("boids-history-{unix_time}.csv");
+        let write = || -> io::Result<()> {
+            let mut out = BufWriter::new(File::create(&path)?);
+            writeln!(out, "seconds,frame_us,boids")?;
+            for sample in &self.samples {
+                writeln!(
+                    out,
+                    "{:.6},{},{}",
+                    sample.at.as_secs_f64(),
+                    sample.frame_time.as_micros(),
+                    sample.boids
+                )?;
+            }
+            out.flush()
+        };
+        match write() {
+            Ok(()) => eprintln!("Wrote {} frames to {path}", self.samples.len()),
+            Err(err) => eprintln!("Could not write {path}: {err}"),
+        }
+    }
+}
+
+/// `--hitch-factor`: watches for frames slower than `factor` times the median of the last
+/// `HITCH_WINDOW` frames and captures them to disk, so a hitch seen once during a rehearsal can
+/// be looked at afterwards. Nothing is flagged until the window has filled, which also covers the
+/// first frames after startup or an idle spell.
+pub struct HitchWatch {
+    factor: f32,
+    recent: VecDeque<Duration>,
+    /// Reused for the median so a frame without a hitch does not allocate.
+    scratch: Vec<Duration>,
+    frame: u64,
+    cooldown: usize,
+}
+
+/// A flagged frame with the frame times leading up to it, oldest first.
+pub struct Hitch {
+    pub frame: u64,
+    pub frame_time: Duration,
+    pub median: Duration,
+    pub recent: Vec<Duration>,
+}
+
+const HITCH_WINDOW: usize = 120;
+/// Frames skipped after a capture, which is a hitch of its own while the snapshot is written.
+const HITCH_COOLDOWN: usize = 30;
+
+impl HitchWatch {
+    pub fn new(factor: f32) -> Self {
+        HitchWatch {
+            factor,
+            recent: VecDeque::with_capacity(HITCH_WINDOW),
+            scratch: Vec::with_capacity(HITCH_WINDOW),
+            frame: 0,
+            cooldown: 0,
+        }
+    }
+
+    /// Forgets the window, for frames that are slow on purpose like the idle throttle.
+    pub fn reset(&mut self) {
+        self.recent.clear();
+    }
+
+    pub fn record(&mut self, frame_time: Duration) -> Option<Hitch> {
+        self.frame += 1;
+        let full = self.recent.len() == HITCH_WINDOW;
+        let hitch = if full && self.cooldown == 0 {
+            self.scratch.clear();
+            self.scratch.extend(&self.recent);
+            let median = *self.scratch.select_nth_unstable(HITCH_WINDOW / 2).1;
+            (frame_time > median.mul_f32(self.factor)).then(|| Hitch {
+                frame: self.frame,
+                frame_time,
+                median,
+                recent: self.recent.iter().copied().collect(),
+            })
+        } else {
+            None
+        };
+        self.cooldown = if hitch.is_some() {
+            HITCH_COOLDOWN
+        } else {
+            self.cooldown.saturating_sub(1)
+        };
+        if full {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(frame_time);
+        hitch
+    }
+}
+
+impl Hitch {
+    /// Writes the frame times and every boid's `x,y,vx,vy` to
+    /// `boids-hitch-<unix time>-<frame>.csv` in the working directory.
+    pub fn capture(&self, label: &str, boids: impl IntoIterator<Item = [Real; 4]>) {
+        tracy_message!(
+            "Hitch: frame {} took {} us, median {} us",
+            self.frame,
+            self.frame_time.as_micros(),
+            self.median.as_micros()
+        );
+        let unix_time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let path = format!("boids-hitch-{unix_time}-{}.csv", self.frame);
+        let write = || -> io::Result<()> {
+            let mut out = BufWriter::new(File::create(&path)?);
+            writeln!(
+                out,
+                "# {label} frame {}: {} us against a median of {} us",
+                self.frame,
+                self.frame_time.as_micros(),
+                self.median.as_micros()
+            )?;
+            writeln!(out, "frame_us")?;
+            for frame_time in &self.recent {
+                writeln!(out, "{}", frame_time.as_micros())?;
+            }
+            writeln!(out, "{}", self.frame_time.as_micros())?;
+            writeln!(out, "x,y,vx,vy")?;
+            for [x, y, vx, vy] in boids {
+                writeln!(out, "{x},{y},{vx},{vy}")?;
+            }
+            out.flush()
+        };
+        match write() {
+            Ok(()) => eprintln!("Hitch in frame {}, wrote {path}", self.frame),
+            Err(err) => eprintln!("Could not write {path}: {err}"),
+        }
+    }
+}
+
+/// `--speed-histogram out.json` buckets the speed of every boid once a second and writes the
+/// histograms when the window closes, so the data-processing part of the talk can run on the
+/// flock from the demo. Each histogram is labeled with the implementation that produced it and
+/// lists only its non-empty buckets:
+///
+/// ```json
+/// {"metric": "speed", "unit": "px/s", "bucket_width": 5, "histograms": [
+///   {"second": 0, "implementation": "threaded", "buckets": [{"min": 45, "max": 50, "count": 4000}]}
+/// ]}
+/// ```
+pub struct SpeedHistogram {
+    path: PathBuf,
+    start: Instant,
+    next_second: u64,
+    histograms: Vec<SpeedSample>,
+}
+
+struct SpeedSample {
+    second: u64,
+    implementation: &'static str,
+    counts: Vec<u32>,
+}
+
+impl SpeedHistogram {
+    /// Twenty buckets up to `MAX_SPEED`, faster boids open further buckets of the same width.
+    pub const BUCKET_WIDTH: Real = MAX_SPEED / 20.0;
+
+    pub fn new(path: PathBuf) -> Self {
+        SpeedHistogram {
+            path,
+            start: Instant::now(),
+            next_second: 0,
+            histograms: vec![],
+        }
+    }
+
+    /// Whether the current second has no histogram yet, checked first so the flock is only
+    /// walked once a second.
+    pub fn is_due(&self) -> bool {
+        self.start.elapsed().as_secs() >= self.next_second
+    }
+
+    pub fn record(
+        &mut self,
+        implementation: &'static str,
+        velocities: impl IntoIterator<Item = RealVec2>,
+    ) {
+        let second = self.start.elapsed().as_secs();
+        let mut counts = vec![];
+        for velocity in velocities {
+            let bucket = (velocity.length() / Self::BUCKET_WIDTH) as usize;
+            if bucket >= counts.len() {
+                counts.resize(bucket + 1, 0);
+            }
+            counts[bucket] += 1;
+        }
+        self.histograms.push(SpeedSample {
+            second,
+            implementation,
+            counts,
+        });
+        self.next_second = second + 1;
+    }
+
+    pub fn write(&self) {
+        let write = || -> io::Result<()> {
+            let mut out = BufWriter::new(File::create(&self.path)?);
+            self.write_json(&mut out)?;
+            out.flush()
+        };
+        match write() {
+            Ok(()) => eprintln!(
+                "Wrote {} speed histograms to {}",
+                self.histograms.len(),
+                self.path.display()
+            ),
+            Err(err) => eprintln!("Could not write {}: {err}", self.path.display()),
+        }
+    }
+
+    fn write_json(&self, out: &mut impl Write) -> io::Result<()> {
+        let width = Self::BUCKET_WIDTH;
+        write!(
+            out,
+            "{{\"metric\": \"speed\", \"unit\": \"px/s\", \"bucket_width\": {width}, \"histograms\": ["
+        )?;
+        for (histogram_idx, histogram) in self.histograms.iter().enumerate() {
+            let separator = if histogram_idx == 0 { "" } else { "," };
+            write!(
+                out,
+                "{separator}\n  {{\"second\": {}, \"implementation\": \"{}\", \"buckets\": [",
+                histogram.second, histogram.implementation
+            )?;
+            let buckets = histogram
+                .counts
+                .iter()
+                .enumerate()
+                .filter(|&(_, &count)| count > 0);
+            for (nonempty_idx, (bucket, count)) in buckets.enumerate() {
+                let separator = if nonempty_idx == 0 { "" } else { ", " };
+                let min = bucket as Real * width;
+                write!(
+                    out,
+                    "{separator}{{\"min\": {min}, \"max\": {}, \"count\": {count}}}",
+                    min + width
+                )?;
+            }
+            write!(out, "]}}")?;
+        }
+        writeln!(out, "\n]}}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hitch_watch_flags_a_slow_frame_once_the_window_is_full() {
+        let frame = Duration::from_millis(10);
+        let slow = Duration::from_millis(40);
+        let mut watch = HitchWatch::new(3.0);
+        assert!(watch.record(slow).is_none());
+        for _ in 0..200 {
+            assert!(watch.record(frame).is_none());
+        }
+        let hitch = watch.record(slow).unwrap();
+        assert_eq!(hitch.median, frame);
+        // The capture itself is slow, the cooldown keeps it from flagging the next frame
+        assert!(watch.record(slow).is_none());
+    }
+    #[test]
+    fn speed_histograms_keep_the_non_empty_buckets_of_each_second() {
+        let mut histogram = SpeedHistogram::new(PathBuf::new());
+        assert!(histogram.is_due());
+        histogram.record(
+            "threaded",
+            [
+                RealVec2::new(3.0, 4.0),
+                RealVec2::new(0.0, -4.0),
+                RealVec2::new(30.0, 40.0),
+            ],
+        );
+        assert!(!histogram.is_due());
+        histogram.record("scalar", []);
+
+        let mut json = vec![];
+        histogram.write_json(&mut json).unwrap();
+        assert_eq!(
+            String::from_utf8(json).unwrap(),
+            "{\"metric\": \"speed\", \"unit\": \"px/s\", \"bucket_width\": 5, \"histograms\": [\n  \
+             {\"second\": 0, \"implementation\": \"threaded\", \"buckets\": [\
+             {\"min\": 0, \"max\": 5, \"count\": 1}, {\"min\": 5, \"max\": 10, \"count\": 1}, \
+             {\"min\": 50, \"max\": 55, \"count\": 1}]},\n  \
+             {\"second\": 0, \"implementation\": \"scalar\", \"buckets\": []}\n]}\n"
+        );
+    }
+}
@@ -0,0 +1,50 @@
+use ggez::graphics::Color;
+
+/// Colors for the background, overlay text and boids. Red triangles on white wash out on a lot of
+/// projectors, hence the alternatives.
+#[derive(Debug, Clone, Copy)]
+pub struct Palette {
+    pub background: Color,
+    pub text: Color,
+    pub boid: Color,
+    pub attracted_boid: Color,
+}
+
+impl Palette {
+    pub const DEFAULT: Palette = Palette {
+        background: Color::WHITE,
+        text: Color::BLACK,
+        boid: Color::RED,
+        attracted_boid: Color::BLUE,
+    };
+
+    pub const HIGH_CONTRAST: Palette = Palette {
+        background: Color::BLACK,
+        text: Color::WHITE,
+        boid: Color::YELLOW,
+        attracted_boid: Color::CYAN,
+    };
+
+    // Okabe-Ito orange and blue, distinguishable with all common forms of color blindness
+    pub const COLORBLIND: Palette = Palette {
+        background: Color::WHITE,
+        text: Color::BLACK,
+        boid: Color::new(0.902, 0.624, 0.0, 1.0),
+        attracted_boid: Color::new(0.0, 0.447, 0.698, 1.0),
+    };
+
+    pub const ALL: [Palette; 3] = [
+        Palette::DEFAULT,
+        Palette::HIGH_CONTRAST,
+        Palette::COLORBLIND,
+    ];
+
+    pub fn from_name(name: &str) -> Option<Palette> {
+        match name {
+            "default" => Some(Palette::DEFAULT),
+            "high-contrast" => Some(Palette::HIGH_CONTRAST),
+            "colorblind" => Some(Palette::COLORBLIND),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,236 @@
+//! What the binaries draw: the snapshot of the flock, the boid mesh and the overlay.
+
+use ggez::graphics::{self, Canvas, Color, DrawParam, Text};
+use ggez::{Context, GameResult};
+use glam::Vec2;
+
+#[cfg(feature = "bandwidth")]
+use crate::bandwidth;
+#[cfg(feature = "diagnostics")]
+use crate::diagnostics;
+#[cfg(feature = "distance_histogram")]
+use crate::distance_histogram;
+#[cfg(feature = "rule_timing")]
+use crate::rule_timing;
+use crate::{rotation_angle, EnergyMeter, Palette, StateChecksum, BOID_SIZE, HELP_WIDTH, INLINING};
+
+/// What the renderer gets to see of the flock, refilled at the end of every update so the draw
+/// code doesn't depend on how an implementation lays its boids out. The buffers live as long as
+/// the state, so after the first frame taking a snapshot does not allocate.
+pub struct SimSnapshot {
+    pub positions: Vec<Vec2>,
+    /// Rotations for the boid mesh, from `rotation_angle`.
+    pub headings: Vec<f32>,
+    pub color: Color,
+}
+
+impl SimSnapshot {
+    pub fn new(color: Color) -> Self {
+        SimSnapshot {
+            positions: vec![],
+            headings: vec![],
+            color,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+
+    /// Sizes the buffers for `len` boids, for implementations that fill them in place.
+    pub fn resize(&mut self, len: usize) {
+        self.positions.resize(len, Vec2::ZERO);
+        self.headings.resize(len, 0.0);
+    }
+
+    /// Refills the snapshot from render space positions and velocities.
+    pub fn capture(&mut self, boids: impl Iterator<Item = (Vec2, Vec2)>, color: Color) {
+        self.positions.clear();
+        self.headings.clear();
+        for (position, velocity) in boids {
+            self.positions.push(position);
+            self.headings.push(rotation_angle(velocity));
+        }
+        self.color = color;
+    }
+
+    pub fn draw_param(&self, boid_idx: usize) -> DrawParam {
+        DrawParam::new()
+            .dest(self.positions[boid_idx])
+            .rotation(self.headings[boid_idx])
+    }
+
+    pub fn draw_params(&self) -> impl Iterator<Item = DrawParam> + '_ {
+        (0..self.len()).map(|boid_idx| self.draw_param(boid_idx))
+    }
+}
+
+// White, so that one mesh serves both boid colors; the tint is applied when drawing
+pub fn make_boid_mesh(ctx: &Context) -> GameResult<graphics::Mesh> {
+    let p1 = Vec2::new(BOID_SIZE, 0f32);
+    let p2 = Vec2::new(0f32, BOID_SIZE / 2.0f32);
+    let p3 = Vec2::new(0f32, -BOID_SIZE / 2.0f32);
+    graphics::Mesh::new_polygon(ctx, graphics::DrawMode::fill(), &[p1, p2, p3], Color::WHITE)
+}
+
+/// The last frame's readings of the instrument features that are built in.
+pub struct Instruments {
+    #[cfg(feature = "diagnostics")]
+    pub diagnostics: diagnostics::Counts,
+    #[cfg(feature = "bandwidth")]
+    pub bandwidth: bandwidth::Stats,
+    #[cfg(feature = "rule_timing")]
+    pub rule_times: rule_timing::Times,
+    #[cfg(feature = "distance_histogram")]
+    pub distances: distance_histogram::Buckets,
+}
+
+impl Instruments {
+    /// Runs the bandwidth triad with the `bandwidth` feature, so this takes a moment then.
+    pub fn new() -> Self {
+        Instruments {
+            #[cfg(feature = "diagnostics")]
+            diagnostics: diagnostics::Counts::default(),
+            #[cfg(feature = "bandwidth")]
+            bandwidth: bandwidth::Stats::measure(),
+            #[cfg(feature = "rule_timing")]
+            rule_times: rule_timing::Times::default(),
+            #[cfg(feature = "distance_histogram")]
+            distances: Default::default(),
+        }
+    }
+
+    /// Collects the counts the update flushed this frame. The bandwidth is taken by the caller,
+    /// which knows the bytes of its step.
+    pub fn take_frame(&mut self) {
+        #[cfg(feature = "diagnostics")]
+        {
+            self.diagnostics = diagnostics::take_frame();
+        }
+        #[cfg(feature = "rule_timing")]
+        {
+            self.rule_times = rule_timing::take_frame();
+        }
+        #[cfg(feature = "distance_histogram")]
+        {
+            self.distances = distance_histogram::take_frame();
+        }
+    }
+}
+
+impl Default for Instruments {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The overlay lines every binary has, from the frame rate at the top left to the help at the
+/// top right. Lines only one implementation has numbers for are drawn by the caller next to it.
+pub struct Overlay<'a> {
+    pub sim_steps: u32,
+    /// Appended to the frame time line.
+    pub step_text: &'a str,
+    pub vsync: bool,
+    pub paused: bool,
+    pub num_boids: usize,
+    pub checksum: Option<&'a StateChecksum>,
+    pub instruments: &'a Instruments,
+    pub energy: Option<&'a EnergyMeter>,
+    /// The full help while it is shown.
+    pub help: Option<String>,
+}
+
+impl Overlay<'_> {
+    pub fn draw(self, ctx: &Context, canvas: &mut Canvas, palette: &Palette, rect_max: Vec2) {
+        let text_at = |canvas: &mut Canvas, text: Text, x: f32, y: f32| {
+            canvas.draw(
+                &text,
+                DrawParam::new().dest(Vec2::new(x, y)).color(palette.text),
+            );
+        };
+
+        text_at(
+            canvas,
+            Text::new(format!("FPS: {:.2}", ctx.time.fps())),
+            10.0,
+            10.0,
+        );
+        text_at(
+            canvas,
+            Text::new(format!(
+                "Frame time: {:.2} us (inlining: {}, {} sim steps{})",
+                ctx.time.delta().as_micros(),
+                INLINING,
+                self.sim_steps,
+                self.step_text
+            )),
+            10.0,
+            20.0,
+        );
+        text_at(
+            canvas,
+            Text::new(format!(
+                "VSync: {} (V)",
+                if self.vsync { "on" } else { "off" }
+            )),
+            10.0,
+            30.0,
+        );
+        if self.paused {
+            text_at(canvas, Text::new("Paused"), 10.0, 40.0);
+        }
+        let boid_count_text = match self.checksum {
+            Some(checksum) => format!("Boids: {} (state {:016x})", self.num_boids, checksum.value),
+            None => format!("Boids: {}", self.num_boids),
+        };
+        text_at(canvas, Text::new(boid_count_text), 10.0, 50.0);
+
+        #[cfg(feature = "diagnostics")]
+        text_at(
+            canvas,
+            Text::new(format!(
+                "Epsilon guard: {}\nZero neighbors: {}\nClamped: {}",
+                self.instruments.diagnostics.epsilon_guard,
+                self.instruments.diagnostics.zero_neighbors,
+                self.instruments.diagnostics.clamped
+            )),
+            10.0,
+            60.0,
+        );
+        #[cfg(feature = "bandwidth")]
+        text_at(
+            canvas,
+            Text::new(self.instruments.bandwidth.text()),
+            10.0,
+            90.0,
+        );
+        #[cfg(feature = "rule_timing")]
+        rule_timing::draw(
+            canvas,
+            Vec2::new(10.0, 120.0),
+            self.instruments.rule_times,
+            palette,
+        );
+        #[cfg(feature = "distance_histogram")]
+        distance_histogram::draw(
+            canvas,
+            Vec2::new(10.0, 180.0),
+            &self.instruments.distances,
+            palette,
+        );
+
+        if let Some(energy) = self.energy {
+            text_at(canvas, Text::new(energy.text()), 10.0, 250.0);
+        }
+
+        let help_text = match self.help {
+            Some(help) => Text::new(help),
+            None => Text::new("Help (F1)"),
+        };
+        text_at(canvas, help_text, rect_max.x - HELP_WIDTH, 10.0);
+    }
+}
@@ -0,0 +1,84 @@
+//! Per-frame time spent in each rule, summed over all boids and threads, for the stacked bar in the
+//! overlay. Same thread-local-then-flush scheme as the diagnostics counts, so timing from worker
+//! threads doesn't contend on the shared totals.
+
+use std::cell::Cell;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use ggez::graphics::{self, Canvas, DrawParam, Text};
+use glam::Vec2;
+
+use crate::Palette;
+
+const BAR_WIDTH: f32 = 240.0;
+const BAR_HEIGHT: f32 = 8.0;
+
+/// Nanoseconds per rule.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Times {
+    pub alignment: u64,
+    pub cohesion: u64,
+    pub separation: u64,
+}
+
+thread_local! {
+    static LOCAL_TIMES: Cell<Times> = Cell::new(Times::default());
+}
+
+static ALIGNMENT_NS: AtomicU64 = AtomicU64::new(0);
+static COHESION_NS: AtomicU64 = AtomicU64::new(0);
+static SEPARATION_NS: AtomicU64 = AtomicU64::new(0);
+
+#[inline(always)]
+pub fn add(time: impl FnOnce(&mut Times)) {
+    LOCAL_TIMES.with(|local| {
+        let mut times = local.get();
+        time(&mut times);
+        local.set(times);
+    });
+}
+
+pub fn flush() {
+    let times = LOCAL_TIMES.take();
+    ALIGNMENT_NS.fetch_add(times.alignment, Ordering::Relaxed);
+    COHESION_NS.fetch_add(times.cohesion, Ordering::Relaxed);
+    SEPARATION_NS.fetch_add(times.separation, Ordering::Relaxed);
+}
+
+pub fn take_frame() -> Times {
+    Times {
+        alignment: ALIGNMENT_NS.swap(0, Ordering::Relaxed),
+        cohesion: COHESION_NS.swap(0, Ordering::Relaxed),
+        separation: SEPARATION_NS.swap(0, Ordering::Relaxed),
+    }
+}
+
+/// A line with each rule's share of the total and a bar split the same way below it.
+pub fn draw(canvas: &mut Canvas, dest: Vec2, times: Times, palette: &Palette) {
+    let total = (times.alignment + times.cohesion + times.separation).max(1) as f32;
+    let rules = [
+        ("alignment", times.alignment, palette.boid),
+        ("cohesion", times.cohesion, palette.attracted_boid),
+        ("separation", times.separation, palette.text),
+    ];
+    let label = rules
+        .iter()
+        .map(|(name, ns, _)| format!("{name} {:.0}%", *ns as f32 / total * 100.0))
+        .collect::<Vec<_>>()
+        .join(", ");
+    canvas.draw(
+        &Text::new(format!("Rules: {label}")),
+        DrawParam::new().dest(dest).color(palette.text),
+    );
+    let mut x = dest.x;
+    for (_, ns, color) in rules {
+        let width = ns as f32 / total * BAR_WIDTH;
+        canvas.draw(
+            &graphics::Quad,
+            DrawParam::new()
+                .dest_rect(graphics::Rect::new(x, dest.y + 12.0, width, BAR_HEIGHT))
+                .color(color),
+        );
+        x += width;
+    }
+}
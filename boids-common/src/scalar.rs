@@ -0,0 +1,479 @@
+//! The flock rules on packed boids, one boid at a time against the whole flock. The threaded
+//! implementation of boids-rs steps these on the rayon pool, and boids-simd-rs falls back to them
+//! on toolchains without `portable_simd`.
+
+#[cfg(feature = "branchless")]
+use glam::BVec2;
+
+use crate::*;
+
+/// Only the hot fields, packed so that a neighbor visit reads nothing else.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Boid {
+    pub position: RealVec2,
+    pub velocity: RealVec2,
+}
+
+impl Boid {
+    pub fn new(position: RealVec2, velocity: RealVec2) -> Self {
+        Boid { position, velocity }
+    }
+
+    #[inline(always)]
+    fn is_close_enough(&self, other: &Boid, max_dist: Real) -> bool {
+        let distance = self.position.distance_squared(other.position);
+        count_diagnostic!(epsilon_guard, distance == 0.0);
+        distance < (max_dist * max_dist) && distance > 0.0
+    }
+
+    #[cfg(not(feature = "branchless"))]
+    #[cfg_attr(feature = "iterators", allow(dead_code))]
+    #[cfg_attr(not(feature = "inline_default"), inline(never))]
+    #[cfg_attr(feature = "inline_always", inline(always))]
+    fn alignment(&self, boids: &[Boid], self_idx: usize) -> RealVec2 {
+        let mut alignment = RealVec2::ZERO;
+        let mut total = 0;
+
+        for other_idx in 0..boids.len() {
+            if other_idx == self_idx {
+                continue;
+            }
+
+            let other = &boids[other_idx];
+            if self.is_close_enough(other, PERCEPTION) {
+                alignment += other.velocity;
+                total += 1;
+            }
+        }
+
+        count_diagnostic!(zero_neighbors, total == 0);
+        if total > 0 {
+            alignment /= total as Real;
+            alignment = alignment.normalize() * MAX_SPEED;
+            alignment -= self.velocity;
+            count_diagnostic!(clamped, alignment.length_squared() > MAX_FORCE * MAX_FORCE);
+            alignment = alignment.clamp_length_max(MAX_FORCE);
+        }
+        alignment
+    }
+
+    #[cfg(not(feature = "branchless"))]
+    #[cfg_attr(feature = "iterators", allow(dead_code))]
+    #[cfg_attr(not(feature = "inline_default"), inline(never))]
+    #[cfg_attr(feature = "inline_always", inline(always))]
+    fn cohesion(&self, boids: &[Boid], self_idx: usize) -> RealVec2 {
+        let mut cohesion = RealVec2::ZERO;
+        let mut total = 0;
+
+        for other_idx in 0..boids.len() {
+            if other_idx == self_idx {
+                continue;
+            }
+
+            let other = &boids[other_idx];
+            if self.is_close_enough(other, PERCEPTION) {
+                cohesion += other.position;
+                total += 1;
+            }
+        }
+
+        count_diagnostic!(zero_neighbors, total == 0);
+        if total > 0 {
+            cohesion /= total as Real;
+            cohesion -= self.position;
+            cohesion = cohesion.normalize() * MAX_SPEED;
+            cohesion -= self.velocity;
+            count_diagnostic!(clamped, cohesion.length_squared() > MAX_FORCE * MAX_FORCE);
+            cohesion = cohesion.clamp_length_max(MAX_FORCE);
+        }
+
+        cohesion
+    }
+
+    #[cfg(not(feature = "branchless"))]
+    #[cfg_attr(feature = "iterators", allow(dead_code))]
+    #[cfg_attr(not(feature = "inline_default"), inline(never))]
+    #[cfg_attr(feature = "inline_always", inline(always))]
+    fn separation(&self, boids: &[Boid], self_idx: usize) -> RealVec2 {
+        let mut separation = RealVec2::ZERO;
+        let mut total_separation = 0;
+
+        for other_idx in 0..boids.len() {
+            if other_idx == self_idx {
+                continue;
+            }
+
+            let other = &boids[other_idx];
+            let distance = self.position.distance(other.position);
+            count_diagnostic!(epsilon_guard, distance == 0.0);
+            record_distance!(distance);
+
+            if distance < SEPARATION && distance > 0.0 {
+                let diff = (self.position - other.position).normalize() / distance;
+                separation += diff;
+                total_separation += 1;
+            }
+        }
+
+        count_diagnostic!(zero_neighbors, total_separation == 0);
+        if total_separation > 0 {
+            separation /= total_separation as Real;
+            separation = separation.normalize() * MAX_SPEED;
+            separation -= self.velocity;
+            count_diagnostic!(clamped, separation.length_squared() > MAX_FORCE * MAX_FORCE);
+            separation = separation.clamp_length_max(MAX_FORCE);
+        }
+
+        separation
+    }
+
+    // The branchless rules mirror the SIMD select logic: every neighbor is accumulated, with a
+    // select or a 0/1 weight standing in for the `if`. The boid itself is left out by walking the
+    // indices on either side of it, as the vertical kernels leave out rotation 0, so that only
+    // coincident neighbors reach the `distance > 0.0` test and the epsilon guard count.
+    #[cfg(feature = "branchless")]
+    #[cfg_attr(feature = "iterators", allow(dead_code))]
+    #[cfg_attr(not(feature = "inline_default"), inline(never))]
+    #[cfg_attr(feature = "inline_always", inline(always))]
+    fn alignment(&self, boids: &[Boid], self_idx: usize) -> RealVec2 {
+        let mut alignment = RealVec2::ZERO;
+        let mut total: Real = 0.0;
+
+        for others in [0..self_idx, self_idx + 1..boids.len()] {
+            for other_idx in others {
+                let other = &boids[other_idx];
+                let is_close = self.is_close_enough(other, PERCEPTION);
+                alignment +=
+                    RealVec2::select(BVec2::splat(is_close), other.velocity, RealVec2::ZERO);
+                total += mask_weight(is_close);
+            }
+        }
+
+        count_diagnostic!(zero_neighbors, total == 0.0);
+        if total > 0.0 {
+            alignment /= total;
+            alignment = alignment.normalize() * MAX_SPEED;
+            alignment -= self.velocity;
+            count_diagnostic!(clamped, alignment.length_squared() > MAX_FORCE * MAX_FORCE);
+            alignment = alignment.clamp_length_max(MAX_FORCE);
+        }
+        alignment
+    }
+
+    #[cfg(feature = "branchless")]
+    #[cfg_attr(feature = "iterators", allow(dead_code))]
+    #[cfg_attr(not(feature = "inline_default"), inline(never))]
+    #[cfg_attr(feature = "inline_always", inline(always))]
+    fn cohesion(&self, boids: &[Boid], self_idx: usize) -> RealVec2 {
+        let mut cohesion = RealVec2::ZERO;
+        let mut total: Real = 0.0;
+
+        for others in [0..self_idx, self_idx + 1..boids.len()] {
+            for other_idx in others {
+                let other = &boids[other_idx];
+                let is_close = self.is_close_enough(other, PERCEPTION);
+                cohesion +=
+                    RealVec2::select(BVec2::splat(is_close), other.position, RealVec2::ZERO);
+                total += mask_weight(is_close);
+            }
+        }
+
+        count_diagnostic!(zero_neighbors, total == 0.0);
+        if total > 0.0 {
+            cohesion /= total;
+            cohesion -= self.position;
+            cohesion = cohesion.normalize() * MAX_SPEED;
+            cohesion -= self.velocity;
+            count_diagnostic!(clamped, cohesion.length_squared() > MAX_FORCE * MAX_FORCE);
+            cohesion = cohesion.clamp_length_max(MAX_FORCE);
+        }
+
+        cohesion
+    }
+
+    #[cfg(feature = "branchless")]
+    #[cfg_attr(feature = "iterators", allow(dead_code))]
+    #[cfg_attr(not(feature = "inline_default"), inline(never))]
+    #[cfg_attr(feature = "inline_always", inline(always))]
+    fn separation(&self, boids: &[Boid], self_idx: usize) -> RealVec2 {
+        let mut separation = RealVec2::ZERO;
+        let mut total_separation: Real = 0.0;
+
+        for others in [0..self_idx, self_idx + 1..boids.len()] {
+            for other_idx in others {
+                let other = &boids[other_idx];
+                let distance = self.position.distance(other.position);
+                count_diagnostic!(epsilon_guard, distance == 0.0);
+                record_distance!(distance);
+
+                // The diff is computed unconditionally and is NaN for a coincident neighbor;
+                // the select throws it away, the same as in the SIMD kernel.
+                let is_close = distance < SEPARATION && distance > 0.0;
+                let diff = (self.position - other.position).normalize() / distance;
+                separation += RealVec2::select(BVec2::splat(is_close), diff, RealVec2::ZERO);
+                total_separation += mask_weight(is_close);
+            }
+        }
+
+        count_diagnostic!(zero_neighbors, total_separation == 0.0);
+        if total_separation > 0.0 {
+            separation /= total_separation;
+            separation = separation.normalize() * MAX_SPEED;
+            separation -= self.velocity;
+            count_diagnostic!(clamped, separation.length_squared() > MAX_FORCE * MAX_FORCE);
+            separation = separation.clamp_length_max(MAX_FORCE);
+        }
+
+        separation
+    }
+
+    // Iterator-adapter versions of the rules. The fold keeps the summation order of the loops, so
+    // both produce bit-identical forces.
+    #[cfg(feature = "iterators")]
+    #[cfg_attr(not(feature = "inline_default"), inline(never))]
+    #[cfg_attr(feature = "inline_always", inline(always))]
+    fn alignment_iter(&self, boids: &[Boid], self_idx: usize) -> RealVec2 {
+        let (alignment, total) = boids
+            .iter()
+            .enumerate()
+            .filter(|&(other_idx, _)| other_idx != self_idx)
+            .map(|(_, other)| other)
+            .filter(|other| self.is_close_enough(other, PERCEPTION))
+            .fold((RealVec2::ZERO, 0), |(sum, count), other| {
+                (sum + other.velocity, count + 1)
+            });
+
+        count_diagnostic!(zero_neighbors, total == 0);
+        if total == 0 {
+            return alignment;
+        }
+        let alignment = (alignment / total as Real).normalize() * MAX_SPEED - self.velocity;
+        count_diagnostic!(clamped, alignment.length_squared() > MAX_FORCE * MAX_FORCE);
+        alignment.clamp_length_max(MAX_FORCE)
+    }
+
+    #[cfg(feature = "iterators")]
+    #[cfg_attr(not(feature = "inline_default"), inline(never))]
+    #[cfg_attr(feature = "inline_always", inline(always))]
+    fn cohesion_iter(&self, boids: &[Boid], self_idx: usize) -> RealVec2 {
+        let (cohesion, total) = boids
+            .iter()
+            .enumerate()
+            .filter(|&(other_idx, _)| other_idx != self_idx)
+            .map(|(_, other)| other)
+            .filter(|other| self.is_close_enough(other, PERCEPTION))
+            .fold((RealVec2::ZERO, 0), |(sum, count), other| {
+                (sum + other.position, count + 1)
+            });
+
+        count_diagnostic!(zero_neighbors, total == 0);
+        if total == 0 {
+            return cohesion;
+        }
+        let cohesion =
+            (cohesion / total as Real - self.position).normalize() * MAX_SPEED - self.velocity;
+        count_diagnostic!(clamped, cohesion.length_squared() > MAX_FORCE * MAX_FORCE);
+        cohesion.clamp_length_max(MAX_FORCE)
+    }
+
+    #[cfg(feature = "iterators")]
+    #[cfg_attr(not(feature = "inline_default"), inline(never))]
+    #[cfg_attr(feature = "inline_always", inline(always))]
+    fn separation_iter(&self, boids: &[Boid], self_idx: usize) -> RealVec2 {
+        let (separation, total_separation) = boids
+            .iter()
+            .enumerate()
+            .filter(|&(other_idx, _)| other_idx != self_idx)
+            .map(|(_, other)| {
+                let other_position = other.position;
+                (other_position, self.position.distance(other_position))
+            })
+            .filter(|&(_, distance)| {
+                count_diagnostic!(epsilon_guard, distance == 0.0);
+                record_distance!(distance);
+                distance < SEPARATION && distance > 0.0
+            })
+            .map(|(other_position, distance)| {
+                (self.position - other_position).normalize() / distance
+            })
+            .fold((RealVec2::ZERO, 0), |(sum, count), diff| {
+                (sum + diff, count + 1)
+            });
+
+        count_diagnostic!(zero_neighbors, total_separation == 0);
+        if total_separation == 0 {
+            return separation;
+        }
+        let separation =
+            (separation / total_separation as Real).normalize() * MAX_SPEED - self.velocity;
+        count_diagnostic!(clamped, separation.length_squared() > MAX_FORCE * MAX_FORCE);
+        separation.clamp_length_max(MAX_FORCE)
+    }
+
+    #[cfg_attr(not(feature = "inline_default"), inline(never))]
+    #[cfg_attr(feature = "inline_always", inline(always))]
+    pub fn calc_acceleration(
+        &self,
+        self_idx: usize,
+        boids: &[Boid],
+        attractors: &[RealVec2],
+        attraction_strength: Real,
+    ) -> RealVec2 {
+        #[cfg(not(feature = "iterators"))]
+        let (alignment, cohesion, separation) = (
+            timed_rule!(alignment, self.alignment(boids, self_idx)),
+            timed_rule!(cohesion, self.cohesion(boids, self_idx)),
+            timed_rule!(separation, self.separation(boids, self_idx)),
+        );
+        #[cfg(feature = "iterators")]
+        let (alignment, cohesion, separation) = (
+            timed_rule!(alignment, self.alignment_iter(boids, self_idx)),
+            timed_rule!(cohesion, self.cohesion_iter(boids, self_idx)),
+            timed_rule!(separation, self.separation_iter(boids, self_idx)),
+        );
+
+        let mut acceleration = alignment;
+        acceleration += cohesion;
+        acceleration += separation;
+
+        if !attractors.is_empty() {
+            let mut attraction = RealVec2::ZERO;
+            for attractor in attractors {
+                attraction += (*attractor - self.position).normalize_or_zero();
+            }
+            acceleration += attraction / attractors.len() as Real * MAX_SPEED * attraction_strength;
+        }
+        assert!(acceleration.is_finite());
+        flush_diagnostics!();
+        flush_rule_timing!();
+        flush_distance_histogram!();
+        acceleration
+    }
+
+    /// Writes `source` moved on by one step of `dt` under `acceleration` into `self`.
+    #[cfg_attr(feature = "static_update", allow(unused_variables))]
+    pub fn update(&mut self, dt: Real, source: &Boid, acceleration: RealVec2) {
+        self.position = source.position;
+        self.velocity = source.velocity;
+
+        let this_frame_acceleration = std::hint::black_box(acceleration * dt);
+        #[cfg(feature = "static_update")]
+        let this_frame_acceleration = RealVec2::ZERO;
+
+        self.velocity += this_frame_acceleration;
+        assert!(self.velocity.is_finite());
+
+        let this_frame_velocity = std::hint::black_box(self.velocity * dt);
+        #[cfg(feature = "static_update")]
+        let this_frame_velocity = RealVec2::ZERO;
+
+        self.position += this_frame_velocity;
+        assert!(self.position.is_finite());
+    }
+
+    /// Wraps the boid around to the opposite edge once it leaves the screen.
+    pub fn edges(&mut self, screen_width: Real, screen_height: Real) {
+        if self.position.x > screen_width {
+            self.position.x = 0.0;
+        } else if self.position.x < 0.0 {
+            self.position.x = screen_width;
+        }
+
+        if self.position.y > screen_height {
+            self.position.y = 0.0;
+        } else if self.position.y < 0.0 {
+            self.position.y = screen_height;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{self, assert_force};
+
+    fn approaching_pair() -> Vec<Boid> {
+        test_support::APPROACHING_PAIR
+            .iter()
+            .map(|&(position, velocity)| Boid::new(position, velocity))
+            .collect()
+    }
+
+    fn ring(count: usize, radius: Real, speed: Real) -> Vec<Boid> {
+        test_support::ring(count, radius, speed)
+            .map(|(position, velocity)| Boid::new(position, velocity))
+            .collect()
+    }
+
+    #[test]
+    fn isolated_boid_feels_no_force() {
+        let boids = vec![
+            Boid::new(RealVec2::new(0.0, 0.0), RealVec2::new(10.0, 0.0)),
+            Boid::new(
+                RealVec2::new(PERCEPTION * 2.0, 0.0),
+                RealVec2::new(0.0, 10.0),
+            ),
+        ];
+        assert_force(boids[0].alignment(&boids, 0), RealVec2::X, 0.0);
+        assert_force(boids[0].cohesion(&boids, 0), RealVec2::X, 0.0);
+        assert_force(boids[0].separation(&boids, 0), RealVec2::X, 0.0);
+    }
+
+    #[test]
+    fn approaching_pair_steers_apart_and_together() {
+        let boids = approaching_pair();
+        assert_force(boids[0].separation(&boids, 0), -RealVec2::X, MAX_FORCE);
+        assert_force(boids[1].separation(&boids, 1), RealVec2::X, MAX_FORCE);
+        assert_force(boids[0].cohesion(&boids, 0), RealVec2::X, MAX_FORCE);
+        assert_force(boids[1].cohesion(&boids, 1), -RealVec2::X, MAX_FORCE);
+        assert_force(boids[0].alignment(&boids, 0), -RealVec2::X, MAX_FORCE);
+    }
+
+    #[test]
+    fn alignment_steers_toward_neighbor_heading() {
+        let boids = vec![
+            Boid::new(RealVec2::new(0.0, 0.0), RealVec2::new(0.0, 50.0)),
+            Boid::new(RealVec2::new(50.0, 0.0), RealVec2::new(0.0, 100.0)),
+        ];
+        assert_force(boids[0].alignment(&boids, 0), RealVec2::Y, 50.0);
+    }
+
+    #[test]
+    fn ring_pulls_inward_and_pushes_outward() {
+        let boids = ring(6, 40.0, 0.0);
+        for idx in 0..boids.len() {
+            let outward = boids[idx].position;
+            assert_force(boids[idx].cohesion(&boids, idx), -outward, MAX_FORCE);
+            assert_force(boids[idx].separation(&boids, idx), outward, MAX_FORCE);
+        }
+    }
+
+    #[test]
+    fn ring_alignment_opposes_own_heading() {
+        let boids = ring(6, 40.0, 10.0);
+        for idx in 0..boids.len() {
+            let heading = boids[idx].velocity;
+            assert_force(boids[idx].alignment(&boids, idx), -heading, MAX_FORCE);
+        }
+    }
+
+    #[cfg(feature = "iterators")]
+    #[test]
+    fn iterator_rules_match_index_loops() {
+        let mut boids = ring(6, 40.0, 10.0);
+        boids.extend(ring(5, 90.0, -20.0));
+        boids.push(Boid::new(RealVec2::ZERO, RealVec2::X));
+        boids.push(Boid::new(RealVec2::ZERO, RealVec2::Y));
+        for (idx, boid) in boids.iter().enumerate() {
+            assert_eq!(
+                boid.alignment_iter(&boids, idx),
+                boid.alignment(&boids, idx)
+            );
+            assert_eq!(boid.cohesion_iter(&boids, idx), boid.cohesion(&boids, idx));
+            assert_eq!(
+                boid.separation_iter(&boids, idx),
+                boid.separation(&boids, idx)
+            );
+        }
+    }
+}
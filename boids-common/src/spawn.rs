@@ -0,0 +1,79 @@
+use glam::Vec2;
+use rand::{Rng, SeedableRng};
+
+/// Where the initial flock is placed, picked with `--spawn`. A tight cluster puts every boid in
+/// every other boid's perception radius, the worst case for the O(n²) rules and the one a spatial
+/// partition is for; the grid is the opposite, with no neighbors to start from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Spawn {
+    Uniform,
+    Cluster,
+    Ring,
+    Grid,
+}
+
+impl Spawn {
+    pub fn from_name(name: &str) -> Option<Spawn> {
+        match name {
+            "uniform" => Some(Spawn::Uniform),
+            "cluster" => Some(Spawn::Cluster),
+            "ring" => Some(Spawn::Ring),
+            "grid" => Some(Spawn::Grid),
+            _ => None,
+        }
+    }
+
+    /// Position of boid `idx` out of `count`.
+    pub fn position(self, idx: usize, count: usize, rect_max: Vec2, rng: &mut impl Rng) -> Vec2 {
+        let center = rect_max / 2.0;
+        match self {
+            Spawn::Uniform => Vec2::new(
+                rng.gen_range(0.0..rect_max.x),
+                rng.gen_range(0.0..rect_max.y),
+            ),
+            Spawn::Cluster => {
+                // Box-Muller, rand 0.8 has no normal distribution without rand_distr
+                let radius = (-2.0 * (1.0 - rng.gen::<f32>()).ln()).sqrt();
+                let angle = rng.gen_range(0.0..std::f32::consts::TAU);
+                center + Vec2::from_angle(angle) * radius * rect_max.min_element() / 16.0
+            }
+            Spawn::Ring => {
+                let angle = rng.gen_range(0.0..std::f32::consts::TAU);
+                center + Vec2::from_angle(angle) * rect_max.min_element() * 0.4
+            }
+            Spawn::Grid => {
+                let aspect = rect_max.x / rect_max.y;
+                let columns = ((count as f32 * aspect).sqrt().ceil() as usize).max(1);
+                let rows = count.div_ceil(columns).max(1);
+                let cell = rect_max / Vec2::new(columns as f32, rows as f32);
+                Vec2::new((idx % columns) as f32 + 0.5, (idx / columns) as f32 + 0.5) * cell
+            }
+        }
+    }
+}
+
+/// `--seed` goes into the low bytes of the ChaCha seed, so the default of 0 is the all-zeroes
+/// seed every run used before.
+pub fn seeded_rng(seed: u64) -> rand_chacha::ChaCha8Rng {
+    let mut bytes = [0; 32];
+    bytes[..8].copy_from_slice(&seed.to_le_bytes());
+    rand_chacha::ChaCha8Rng::from_seed(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grid_spawn_fills_the_screen_without_overlaps() {
+        let rect_max = Vec2::new(1080.0, 800.0);
+        let mut rng = seeded_rng(0);
+        let positions: Vec<Vec2> = (0..100)
+            .map(|idx| Spawn::Grid.position(idx, 100, rect_max, &mut rng))
+            .collect();
+        for (idx, position) in positions.iter().enumerate() {
+            assert!(position.cmpgt(Vec2::ZERO).all() && position.cmplt(rect_max).all());
+            assert!(positions[..idx].iter().all(|other| other != position));
+        }
+    }
+}
@@ -0,0 +1,32 @@
+//! Fixtures shared by the rule tests of every implementation.
+
+use crate::{Real, RealVec2};
+
+/// Two boids closing in on each other head-on, half a perception radius apart.
+pub const APPROACHING_PAIR: [(RealVec2, RealVec2); 2] = [
+    (RealVec2::new(0.0, 0.0), RealVec2::new(10.0, 0.0)),
+    (RealVec2::new(50.0, 0.0), RealVec2::new(-10.0, 0.0)),
+];
+
+pub fn assert_force(force: RealVec2, direction: RealVec2, magnitude: Real) {
+    assert!(
+        (force.length() - magnitude).abs() < 1e-3,
+        "expected magnitude {magnitude}, got {force}"
+    );
+    if magnitude > 0.0 {
+        assert!(
+            force.normalize().dot(direction.normalize()) > 1.0 - 1e-4,
+            "expected direction {direction}, got {force}"
+        );
+    }
+}
+
+/// Positions and velocities of `count` boids evenly spaced on a circle around the origin, each
+/// heading counterclockwise along it.
+pub fn ring(count: usize, radius: Real, speed: Real) -> impl Iterator<Item = (RealVec2, RealVec2)> {
+    (0..count).map(move |idx| {
+        let direction =
+            RealVec2::from_angle(idx as Real * std::f64::consts::TAU as Real / count as Real);
+        (direction * radius, direction.perp() * speed)
+    })
+}
@@ -0,0 +1,68 @@
+use std::time::Duration;
+
+use crate::util::*;
+
+/// The shared command line options plus the ones of this binary.
+pub type Config = boids_common::Config<ScalarOptions>;
+
+#[derive(Clone, Default)]
+pub struct ScalarOptions {
+    pub auto_threads: bool,
+    pub attract_mode: bool,
+    /// Threaded only: start with the `par_chunks_mut` update, in chunks of this many boids.
+    pub par_chunks: Option<usize>,
+    /// Scalar only: CPU time per frame the update may spend before carrying the step over.
+    pub frame_budget: Option<Duration>,
+}
+
+impl Options for ScalarOptions {
+    const NUM_BOIDS: u16 = 100;
+
+    fn parse_arg(&mut self, arg: &str, args: &mut dyn Iterator<Item = String>) -> bool {
+        match arg {
+            "--auto-threads" => self.auto_threads = true,
+            "--attract-mode" => self.attract_mode = true,
+            "--par-chunks" => {
+                if let Some(chunk_len) = next_value::<usize>(args, arg) {
+                    self.par_chunks = Some(chunk_len.max(1));
+                }
+            }
+            "--frame-budget-ms" => {
+                if let Some(budget_ms) = next_value::<f32>(args, arg) {
+                    // A zero budget would never let a step start, so the flock would freeze
+                    match Duration::try_from_secs_f32(budget_ms / 1000.0) {
+                        Ok(budget) if !budget.is_zero() => self.frame_budget = Some(budget),
+                        _ => eprintln!(
+                            "Expected a positive number of milliseconds for `{arg}`, running without a budget"
+                        ),
+                    }
+                }
+            }
+            _ => return false,
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Config {
+        Config::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn frame_budget_must_be_positive() {
+        for budget_ms in ["0", "-4", "0.0000001", "NaN", "inf"] {
+            let config = parse(&["--frame-budget-ms", budget_ms]);
+            assert_eq!(config.options.frame_budget, None, "for {budget_ms}");
+        }
+        let config = parse(&["--frame-budget-ms", "2.5", "4000"]);
+        assert_eq!(
+            config.options.frame_budget,
+            Some(Duration::from_micros(2500))
+        );
+        assert_eq!(config.num_boids, 4000);
+    }
+}
@@ -1,19 +1,20 @@
 use std::cell::RefCell;
+use std::time::{Duration, Instant};
 
-use ggez::event::EventHandler;
-use ggez::graphics::{self, Color, DrawParam, Text};
-use ggez::input::keyboard::KeyCode;
-use ggez::{Context, GameResult};
+#[cfg(feature = "branchless")]
+use glam::BVec2;
 use glam::Vec2;
-use rand::{Rng, SeedableRng};
+use rand::Rng;
 
+use crate::config::Config;
+use crate::frame_loop::Simulation;
 use crate::util::*;
 
 #[repr(C)]
 struct Boid {
-    position: Vec2,
-    velocity: Vec2,
-    acceleration: Vec2,
+    position: RealVec2,
+    velocity: RealVec2,
+    acceleration: RealVec2,
 
     #[cfg(not(feature = "no_life_history"))]
     life_history: [i32; 512],
@@ -28,11 +29,11 @@ type BoidRef = Box<BoidCell>;
 type BoidRef = BoidCell;
 
 impl Boid {
-    fn new(position: Vec2, velocity: Vec2) -> Self {
+    fn new(position: RealVec2, velocity: RealVec2) -> Self {
         Boid {
             position,
             velocity,
-            acceleration: Vec2::ZERO,
+            acceleration: RealVec2::ZERO,
 
             #[cfg(not(feature = "no_life_history"))]
             life_history: [0; 512],
@@ -41,21 +42,26 @@ impl Boid {
 
     #[cfg(not(feature = "pre_square"))]
     #[inline(always)]
-    fn is_close_enough(&self, other: &Boid, max_dist: f32) -> bool {
+    fn is_close_enough(&self, other: &Boid, max_dist: Real) -> bool {
         let distance = self.position.distance(other.position);
+        count_diagnostic!(epsilon_guard, distance == 0.0);
         distance < max_dist && distance > 0.0
     }
 
     #[cfg(feature = "pre_square")]
     #[inline(always)]
-    fn is_close_enough(&self, other: &Boid, max_dist: f32) -> bool {
+    fn is_close_enough(&self, other: &Boid, max_dist: Real) -> bool {
         let distance = self.position.distance_squared(other.position);
+        count_diagnostic!(epsilon_guard, distance == 0.0);
         distance < (max_dist * max_dist) && distance > 0.0
     }
 
-    #[inline(never)]
-    fn alignment(&self, boids: &[BoidRef], self_idx: usize) -> Vec2 {
-        let mut alignment = Vec2::ZERO;
+    #[cfg(not(feature = "branchless"))]
+    #[cfg_attr(feature = "iterators", allow(dead_code))]
+    #[cfg_attr(not(feature = "inline_default"), inline(never))]
+    #[cfg_attr(feature = "inline_always", inline(always))]
+    fn alignment(&self, boids: &[BoidRef], self_idx: usize) -> RealVec2 {
+        let mut alignment = RealVec2::ZERO;
         let mut total = 0;
 
         for other_idx in 0..boids.len() {
@@ -70,18 +76,23 @@ impl Boid {
             }
         }
 
+        count_diagnostic!(zero_neighbors, total == 0);
         if total > 0 {
-            alignment /= total as f32;
+            alignment /= total as Real;
             alignment = alignment.normalize() * MAX_SPEED;
             alignment -= self.velocity;
+            count_diagnostic!(clamped, alignment.length_squared() > MAX_FORCE * MAX_FORCE);
             alignment = alignment.clamp_length_max(MAX_FORCE);
         }
         alignment
     }
 
-    #[inline(never)]
-    fn cohesion(&self, boids: &[BoidRef], self_idx: usize) -> Vec2 {
-        let mut cohesion = Vec2::ZERO;
+    #[cfg(not(feature = "branchless"))]
+    #[cfg_attr(feature = "iterators", allow(dead_code))]
+    #[cfg_attr(not(feature = "inline_default"), inline(never))]
+    #[cfg_attr(feature = "inline_always", inline(always))]
+    fn cohesion(&self, boids: &[BoidRef], self_idx: usize) -> RealVec2 {
+        let mut cohesion = RealVec2::ZERO;
         let mut total = 0;
 
         for other_idx in 0..boids.len() {
@@ -96,20 +107,25 @@ impl Boid {
             }
         }
 
+        count_diagnostic!(zero_neighbors, total == 0);
         if total > 0 {
-            cohesion /= total as f32;
+            cohesion /= total as Real;
             cohesion -= self.position;
             cohesion = cohesion.normalize() * MAX_SPEED;
             cohesion -= self.velocity;
+            count_diagnostic!(clamped, cohesion.length_squared() > MAX_FORCE * MAX_FORCE);
             cohesion = cohesion.clamp_length_max(MAX_FORCE);
         }
 
         cohesion
     }
 
-    #[inline(never)]
-    fn separation(&self, boids: &[BoidRef], self_idx: usize) -> Vec2 {
-        let mut separation = Vec2::ZERO;
+    #[cfg(not(feature = "branchless"))]
+    #[cfg_attr(feature = "iterators", allow(dead_code))]
+    #[cfg_attr(not(feature = "inline_default"), inline(never))]
+    #[cfg_attr(feature = "inline_always", inline(always))]
+    fn separation(&self, boids: &[BoidRef], self_idx: usize) -> RealVec2 {
+        let mut separation = RealVec2::ZERO;
         let mut total_separation = 0;
 
         for other_idx in 0..boids.len() {
@@ -119,6 +135,8 @@ impl Boid {
 
             let other = boids[other_idx].borrow();
             let distance = self.position.distance(other.position);
+            count_diagnostic!(epsilon_guard, distance == 0.0);
+            record_distance!(distance);
 
             if distance < SEPARATION && distance > 0.0 {
                 let diff = (self.position - other.position).normalize() / distance;
@@ -127,61 +145,267 @@ impl Boid {
             }
         }
 
+        count_diagnostic!(zero_neighbors, total_separation == 0);
         if total_separation > 0 {
-            separation /= total_separation as f32;
+            separation /= total_separation as Real;
             separation = separation.normalize() * MAX_SPEED;
             separation -= self.velocity;
+            count_diagnostic!(clamped, separation.length_squared() > MAX_FORCE * MAX_FORCE);
             separation = separation.clamp_length_max(MAX_FORCE);
         }
 
         separation
     }
 
-    #[inline(never)]
+    // The branchless rules mirror the SIMD select logic in scalar code: every neighbor is
+    // accumulated, with a select or a 0/1 weight standing in for the `if`. The boid itself is left
+    // out by walking the indices on either side of it, as the vertical kernels leave out rotation
+    // 0, which also keeps the loop off its cell while that is mutably borrowed. Only coincident
+    // neighbors reach the `distance > 0.0` test and the epsilon guard count.
+    #[cfg(feature = "branchless")]
+    #[cfg_attr(feature = "iterators", allow(dead_code))]
+    #[cfg_attr(not(feature = "inline_default"), inline(never))]
+    #[cfg_attr(feature = "inline_always", inline(always))]
+    fn alignment(&self, boids: &[BoidRef], self_idx: usize) -> RealVec2 {
+        let mut alignment = RealVec2::ZERO;
+        let mut total: Real = 0.0;
+
+        for others in [0..self_idx, self_idx + 1..boids.len()] {
+            for other_idx in others {
+                let other = boids[other_idx].borrow();
+                let is_close = self.is_close_enough(&other, PERCEPTION);
+                alignment +=
+                    RealVec2::select(BVec2::splat(is_close), other.velocity, RealVec2::ZERO);
+                total += mask_weight(is_close);
+            }
+        }
+
+        count_diagnostic!(zero_neighbors, total == 0.0);
+        if total > 0.0 {
+            alignment /= total;
+            alignment = alignment.normalize() * MAX_SPEED;
+            alignment -= self.velocity;
+            count_diagnostic!(clamped, alignment.length_squared() > MAX_FORCE * MAX_FORCE);
+            alignment = alignment.clamp_length_max(MAX_FORCE);
+        }
+        alignment
+    }
+
+    #[cfg(feature = "branchless")]
+    #[cfg_attr(feature = "iterators", allow(dead_code))]
+    #[cfg_attr(not(feature = "inline_default"), inline(never))]
+    #[cfg_attr(feature = "inline_always", inline(always))]
+    fn cohesion(&self, boids: &[BoidRef], self_idx: usize) -> RealVec2 {
+        let mut cohesion = RealVec2::ZERO;
+        let mut total: Real = 0.0;
+
+        for others in [0..self_idx, self_idx + 1..boids.len()] {
+            for other_idx in others {
+                let other = boids[other_idx].borrow();
+                let is_close = self.is_close_enough(&other, PERCEPTION);
+                cohesion +=
+                    RealVec2::select(BVec2::splat(is_close), other.position, RealVec2::ZERO);
+                total += mask_weight(is_close);
+            }
+        }
+
+        count_diagnostic!(zero_neighbors, total == 0.0);
+        if total > 0.0 {
+            cohesion /= total;
+            cohesion -= self.position;
+            cohesion = cohesion.normalize() * MAX_SPEED;
+            cohesion -= self.velocity;
+            count_diagnostic!(clamped, cohesion.length_squared() > MAX_FORCE * MAX_FORCE);
+            cohesion = cohesion.clamp_length_max(MAX_FORCE);
+        }
+
+        cohesion
+    }
+
+    #[cfg(feature = "branchless")]
+    #[cfg_attr(feature = "iterators", allow(dead_code))]
+    #[cfg_attr(not(feature = "inline_default"), inline(never))]
+    #[cfg_attr(feature = "inline_always", inline(always))]
+    fn separation(&self, boids: &[BoidRef], self_idx: usize) -> RealVec2 {
+        let mut separation = RealVec2::ZERO;
+        let mut total_separation: Real = 0.0;
+
+        for others in [0..self_idx, self_idx + 1..boids.len()] {
+            for other_idx in others {
+                let other = boids[other_idx].borrow();
+                let distance = self.position.distance(other.position);
+                count_diagnostic!(epsilon_guard, distance == 0.0);
+                record_distance!(distance);
+
+                // The diff is computed unconditionally and is NaN for a coincident neighbor;
+                // the select throws it away, the same as in the SIMD kernel.
+                let is_close = distance < SEPARATION && distance > 0.0;
+                let diff = (self.position - other.position).normalize() / distance;
+                separation += RealVec2::select(BVec2::splat(is_close), diff, RealVec2::ZERO);
+                total_separation += mask_weight(is_close);
+            }
+        }
+
+        count_diagnostic!(zero_neighbors, total_separation == 0.0);
+        if total_separation > 0.0 {
+            separation /= total_separation;
+            separation = separation.normalize() * MAX_SPEED;
+            separation -= self.velocity;
+            count_diagnostic!(clamped, separation.length_squared() > MAX_FORCE * MAX_FORCE);
+            separation = separation.clamp_length_max(MAX_FORCE);
+        }
+
+        separation
+    }
+
+    // The same rules written with iterator adapters instead of index loops. The fold keeps the
+    // summation order of the loops, so both produce bit-identical forces and any difference in
+    // speed comes from the generated code alone.
+    #[cfg(feature = "iterators")]
+    #[cfg_attr(not(feature = "inline_default"), inline(never))]
+    #[cfg_attr(feature = "inline_always", inline(always))]
+    fn alignment_iter(&self, boids: &[BoidRef], self_idx: usize) -> RealVec2 {
+        let (alignment, total) = boids
+            .iter()
+            .enumerate()
+            .filter(|&(other_idx, _)| other_idx != self_idx)
+            .map(|(_, other)| other.borrow())
+            .filter(|other| self.is_close_enough(other, PERCEPTION))
+            .fold((RealVec2::ZERO, 0), |(sum, count), other| {
+                (sum + other.velocity, count + 1)
+            });
+
+        count_diagnostic!(zero_neighbors, total == 0);
+        if total == 0 {
+            return alignment;
+        }
+        let alignment = (alignment / total as Real).normalize() * MAX_SPEED - self.velocity;
+        count_diagnostic!(clamped, alignment.length_squared() > MAX_FORCE * MAX_FORCE);
+        alignment.clamp_length_max(MAX_FORCE)
+    }
+
+    #[cfg(feature = "iterators")]
+    #[cfg_attr(not(feature = "inline_default"), inline(never))]
+    #[cfg_attr(feature = "inline_always", inline(always))]
+    fn cohesion_iter(&self, boids: &[BoidRef], self_idx: usize) -> RealVec2 {
+        let (cohesion, total) = boids
+            .iter()
+            .enumerate()
+            .filter(|&(other_idx, _)| other_idx != self_idx)
+            .map(|(_, other)| other.borrow())
+            .filter(|other| self.is_close_enough(other, PERCEPTION))
+            .fold((RealVec2::ZERO, 0), |(sum, count), other| {
+                (sum + other.position, count + 1)
+            });
+
+        count_diagnostic!(zero_neighbors, total == 0);
+        if total == 0 {
+            return cohesion;
+        }
+        let cohesion =
+            (cohesion / total as Real - self.position).normalize() * MAX_SPEED - self.velocity;
+        count_diagnostic!(clamped, cohesion.length_squared() > MAX_FORCE * MAX_FORCE);
+        cohesion.clamp_length_max(MAX_FORCE)
+    }
+
+    #[cfg(feature = "iterators")]
+    #[cfg_attr(not(feature = "inline_default"), inline(never))]
+    #[cfg_attr(feature = "inline_always", inline(always))]
+    fn separation_iter(&self, boids: &[BoidRef], self_idx: usize) -> RealVec2 {
+        let (separation, total_separation) = boids
+            .iter()
+            .enumerate()
+            .filter(|&(other_idx, _)| other_idx != self_idx)
+            .map(|(_, other)| {
+                let other_position = other.borrow().position;
+                (other_position, self.position.distance(other_position))
+            })
+            .filter(|&(_, distance)| {
+                count_diagnostic!(epsilon_guard, distance == 0.0);
+                record_distance!(distance);
+                distance < SEPARATION && distance > 0.0
+            })
+            .map(|(other_position, distance)| {
+                (self.position - other_position).normalize() / distance
+            })
+            .fold((RealVec2::ZERO, 0), |(sum, count), diff| {
+                (sum + diff, count + 1)
+            });
+
+        count_diagnostic!(zero_neighbors, total_separation == 0);
+        if total_separation == 0 {
+            return separation;
+        }
+        let separation =
+            (separation / total_separation as Real).normalize() * MAX_SPEED - self.velocity;
+        count_diagnostic!(clamped, separation.length_squared() > MAX_FORCE * MAX_FORCE);
+        separation.clamp_length_max(MAX_FORCE)
+    }
+
+    #[cfg_attr(not(feature = "inline_default"), inline(never))]
+    #[cfg_attr(feature = "inline_always", inline(always))]
     fn apply_behavior(
         &mut self,
         self_idx: usize,
         boids: &[BoidRef],
-        mouse_pos: Vec2,
-        is_attracted: bool,
+        attractors: &[RealVec2],
+        attraction_strength: Real,
     ) {
-        let alignment = self.alignment(boids, self_idx);
-        let cohesion = self.cohesion(boids, self_idx);
-        let separation = self.separation(boids, self_idx);
+        #[cfg(not(feature = "iterators"))]
+        let (alignment, cohesion, separation) = (
+            timed_rule!(alignment, self.alignment(boids, self_idx)),
+            timed_rule!(cohesion, self.cohesion(boids, self_idx)),
+            timed_rule!(separation, self.separation(boids, self_idx)),
+        );
+        #[cfg(feature = "iterators")]
+        let (alignment, cohesion, separation) = (
+            timed_rule!(alignment, self.alignment_iter(boids, self_idx)),
+            timed_rule!(cohesion, self.cohesion_iter(boids, self_idx)),
+            timed_rule!(separation, self.separation_iter(boids, self_idx)),
+        );
 
         self.acceleration = alignment;
         self.acceleration += cohesion;
         self.acceleration += separation;
 
-        if is_attracted {
-            let attraction = (mouse_pos - self.position).normalize() * MAX_SPEED;
-            self.acceleration += attraction;
+        if !attractors.is_empty() {
+            let mut attraction = RealVec2::ZERO;
+            for attractor in attractors {
+                attraction += (*attractor - self.position).normalize_or_zero();
+            }
+            self.acceleration +=
+                attraction / attractors.len() as Real * MAX_SPEED * attraction_strength;
         }
         assert!(self.acceleration.is_finite());
+        flush_diagnostics!();
+        flush_rule_timing!();
+        flush_distance_histogram!();
     }
 
-    fn update(&mut self, dt: f32, rng: &mut rand_chacha::ChaCha8Rng) {
+    fn update(&mut self, dt: Real, rng: &mut rand_chacha::ChaCha8Rng) {
         let this_frame_acceleration = std::hint::black_box(self.acceleration * dt);
         #[cfg(feature = "static_update")]
-        let this_frame_acceleration = Vec2::ZERO;
+        let this_frame_acceleration = RealVec2::ZERO;
 
         self.velocity += this_frame_acceleration;
         assert!(self.velocity.is_finite());
 
         let this_frame_velocity = std::hint::black_box(self.velocity * dt);
         #[cfg(feature = "static_update")]
-        let this_frame_velocity = Vec2::ZERO;
+        let this_frame_velocity = RealVec2::ZERO;
 
         self.position += this_frame_velocity;
         assert!(self.position.is_finite());
 
         #[cfg(not(feature = "no_life_history"))]
         {
-            std::hint::black_box(self.life_history[rng.gen_range(0..self.life_history.len())] += 1);
+            let history_idx = rng.gen_range(0..self.life_history.len());
+            self.life_history[history_idx] += 1;
+            std::hint::black_box(&mut self.life_history[history_idx]);
         }
     }
 
-    fn edges(&mut self, screen_width: f32, screen_height: f32) {
+    fn edges(&mut self, screen_width: Real, screen_height: Real) {
         if self.position.x > screen_width {
             self.position.x = 0.0;
         } else if self.position.x < 0.0 {
@@ -194,53 +418,149 @@ impl Boid {
             self.position.y = screen_height;
         }
     }
+}
 
-    fn draw(&self, canvas: &mut graphics::Canvas, boid_mesh: &graphics::Mesh) -> GameResult {
-        let angle = self.velocity.y.atan2(self.velocity.x);
-        canvas.draw(
-            boid_mesh,
-            graphics::DrawParam::new()
-                .dest(self.position)
-                .rotation(angle),
-        );
-        Ok(())
+/// Every boid visits every other boid once per rule. A visit pulls in the cache line holding the
+/// neighbor's hot fields and nothing useful past it: the rest of a full `Boid` is life history.
+#[cfg(feature = "bandwidth")]
+const NEIGHBOR_BYTES: usize = 3 * bandwidth::hot_line_bytes(std::mem::size_of::<BoidCell>());
+#[cfg(feature = "bandwidth")]
+const WRITTEN_BYTES: usize = bandwidth::hot_line_bytes(std::mem::size_of::<BoidCell>());
+
+/// `--frame-budget-ms`: the step is split into slices that can be resumed at any boid. Each
+/// frame runs boids until the budget is spent and carries the rest of the step over, so the frame
+/// rate holds up while the flock moves in slow motion. Every boid of a step moves by the `dt` of
+/// the frame the step started in, and at most `sim_steps` steps finish per frame.
+#[derive(Clone, Copy)]
+struct FrameBudget {
+    budget: Duration,
+    next_boid: usize,
+    step_dt: Real,
+}
+
+impl FrameBudget {
+    fn new(budget: Duration) -> Self {
+        FrameBudget {
+            budget,
+            next_boid: 0,
+            step_dt: 0.0,
+        }
     }
 }
 
-pub struct MainState {
+/// The boxed per-boid flock, stepped one boid at a time on the main thread.
+pub struct Flock {
     boids: Vec<BoidRef>,
     unused_boids: Vec<BoidRef>,
-    is_attracted: bool,
     rect_max: Vec2,
     rng: rand_chacha::ChaCha8Rng,
+    extra_work: u32,
+    frame_budget: Option<FrameBudget>,
 }
 
-impl MainState {
-    pub fn new(num_boids: u16, rect_max: Vec2) -> GameResult<MainState> {
-        let mut rng = rand_chacha::ChaCha8Rng::from_seed([0; 32]);
+impl Flock {
+    // With the `threaded` feature the run starts on the threaded flock and only switches here
+    #[cfg_attr(feature = "threaded", allow(dead_code))]
+    pub fn new(config: &Config, rect_max: Vec2) -> Self {
+        let mut rng = seeded_rng(config.seed);
+        let num_boids = usize::from(config.num_boids);
         let mut boids = vec![];
         let mut unesed_boids = vec![];
-        for _ in 0..num_boids {
-            boids.push(Self::new_random_boid(rect_max, &mut rng));
+        for boid_idx in 0..num_boids {
+            let position = config
+                .spawn
+                .position(boid_idx, num_boids, rect_max, &mut rng);
+            boids.push(Self::new_random_boid(position, &mut rng));
             // For each boid, create 8-15 unused boids to test the performance of the memory allocator
             for _ in 0..rng.gen_range(8..16) {
-                unesed_boids.push(Self::new_random_boid(rect_max, &mut rng));
+                unesed_boids.push(Self::new_uniform_boid(rect_max, &mut rng));
             }
         }
-        Ok(MainState {
+        Flock {
             boids,
             unused_boids: unesed_boids,
-            is_attracted: false,
             rect_max,
             rng,
-        })
+            extra_work: config.extra_work,
+            frame_budget: config.options.frame_budget.map(FrameBudget::new),
+        }
     }
 
-    fn new_random_boid(rect_max: Vec2, rng: &mut rand_chacha::ChaCha8Rng) -> BoidRef {
+    /// Picks up the flock another implementation left behind.
+    pub fn from_boids(config: &Config, rect_max: Vec2, boids: &[(RealVec2, RealVec2)]) -> Self {
+        Flock {
+            boids: boids
+                .iter()
+                .map(|&(position, velocity)| {
+                    let boid_cell = RefCell::new(Boid::new(position, velocity));
+
+                    #[cfg(not(feature = "no_boxing"))]
+                    return Box::new(boid_cell);
+
+                    #[cfg(feature = "no_boxing")]
+                    return boid_cell;
+                })
+                .collect(),
+            unused_boids: vec![],
+            rect_max,
+            rng: seeded_rng(config.seed),
+            extra_work: config.extra_work,
+            frame_budget: config.options.frame_budget.map(FrameBudget::new),
+        }
+    }
+
+    fn add_boids(&mut self) {
+        tracy_scope!("add_boids");
+        self.unused_boids.clear();
+        for _ in 0..10 {
+            self.boids
+                .push(Self::new_uniform_boid(self.rect_max, &mut self.rng));
+            for _ in 0..self.rng.gen_range(8..16) {
+                self.unused_boids
+                    .push(Self::new_uniform_boid(self.rect_max, &mut self.rng));
+            }
+        }
+    }
+
+    fn remove_boids(&mut self) {
+        tracy_scope!("remove_boids");
+        self.unused_boids.clear();
+        for _ in 0..10 {
+            self.boids.pop();
+            for _ in 0..self.rng.gen_range(8..16) {
+                self.unused_boids
+                    .push(Self::new_uniform_boid(self.rect_max, &mut self.rng));
+            }
+        }
+    }
+
+    fn step_boid(
+        &mut self,
+        boid_idx: usize,
+        dt: Real,
+        rect_max: RealVec2,
+        attractors: &[RealVec2],
+        attraction_strength: Real,
+    ) {
+        let mut boid = self.boids[boid_idx].borrow_mut(); // Safety: we check the index to avoid borrowing self
+        boid.apply_behavior(boid_idx, &self.boids, attractors, attraction_strength);
+        extra_work(boid_idx, self.extra_work);
+        boid.update(dt, &mut self.rng);
+        boid.edges(rect_max.x, rect_max.y);
+    }
+
+    // Boids added and churned at runtime ignore `--spawn`
+    fn new_uniform_boid(rect_max: Vec2, rng: &mut rand_chacha::ChaCha8Rng) -> BoidRef {
+        let position = Spawn::Uniform.position(0, 1, rect_max, rng);
+        Self::new_random_boid(position, rng)
+    }
+
+    fn new_random_boid(position: Vec2, rng: &mut rand_chacha::ChaCha8Rng) -> BoidRef {
+        // Randomized in f32 either way, so the f32 and f64 builds start from the same flock
         let new_boid = |position: Vec2, vel_angle: f32| {
             let boid = Boid::new(
-                position,
-                Vec2::new(vel_angle.cos(), vel_angle.sin()) * MAX_SPEED / 2.0,
+                to_real(position),
+                to_real(Vec2::new(vel_angle.cos(), vel_angle.sin())) * MAX_SPEED / 2.0,
             );
             let boid_cell = RefCell::new(boid);
 
@@ -251,118 +571,174 @@ impl MainState {
             return boid_cell;
         };
 
-        new_boid(
-            Vec2::new(
-                rng.gen_range(0.0..rect_max.x),
-                rng.gen_range(0.0..rect_max.y),
-            ),
-            rng.gen_range(0.0..std::f32::consts::TAU),
-        )
-    }
-
-    fn make_boid_mesh(&self, ctx: &mut Context) -> GameResult<graphics::Mesh> {
-        let p1 = Vec2::new(BOID_SIZE, 0f32);
-        let p2 = Vec2::new(0f32, BOID_SIZE / 2.0f32);
-        let p3 = Vec2::new(0f32, -BOID_SIZE / 2.0f32);
-        graphics::Mesh::new_polygon(
-            ctx,
-            graphics::DrawMode::fill(),
-            &[p1, p2, p3],
-            if self.is_attracted {
-                Color::BLUE
-            } else {
-                Color::RED
-            },
-        )
+        new_boid(position, rng.gen_range(0.0..std::f32::consts::TAU))
     }
 }
 
-impl EventHandler for MainState {
-    fn update(&mut self, ctx: &mut Context) -> GameResult {
-        tracy_scope!("update");
-        if ctx.keyboard.is_key_just_pressed(KeyCode::Up) {
-            tracy_scope!("add_boids");
-            self.unused_boids.clear();
-            for _ in 0..10 {
-                self.boids
-                    .push(Self::new_random_boid(self.rect_max, &mut self.rng));
-                for _ in 0..self.rng.gen_range(8..16) {
-                    self.unused_boids
-                        .push(Self::new_random_boid(self.rect_max, &mut self.rng));
+impl Simulation for Flock {
+    fn name(&self) -> &'static str {
+        "scalar"
+    }
+
+    fn num_boids(&self) -> usize {
+        self.boids.len()
+    }
+
+    fn position(&self, boid_idx: usize) -> RealVec2 {
+        self.boids[boid_idx].borrow().position
+    }
+
+    fn boids(&self) -> impl Iterator<Item = (RealVec2, RealVec2)> + '_ {
+        self.boids.iter().map(|boid_cell| {
+            let boid = boid_cell.borrow();
+            (boid.position, boid.velocity)
+        })
+    }
+
+    fn step(
+        &mut self,
+        steps: u32,
+        dt: Real,
+        rect_max: RealVec2,
+        attractors: &[RealVec2],
+        attraction_strength: Real,
+    ) -> u32 {
+        let Some(mut budget) = self.frame_budget else {
+            alloc_free!("update_boids", {
+                for _ in 0..steps {
+                    for boid_idx in 0..self.boids.len() {
+                        self.step_boid(boid_idx, dt, rect_max, attractors, attraction_strength);
+                    }
                 }
-            }
-        } else if ctx.keyboard.is_key_just_pressed(KeyCode::Down) {
-            tracy_scope!("remove_boids");
-            self.unused_boids.clear();
-            for _ in 0..10 {
-                self.boids.pop();
-                for _ in 0..self.rng.gen_range(8..16) {
-                    self.unused_boids
-                        .push(Self::new_random_boid(self.rect_max, &mut self.rng));
+            });
+            return steps;
+        };
+        let deadline = Instant::now() + budget.budget;
+        let mut completed_steps = 0;
+        alloc_free!("update_boids", {
+            while completed_steps < steps && Instant::now() < deadline {
+                if budget.next_boid == 0 {
+                    budget.step_dt = dt;
+                }
+                // Past the end when boids were removed mid-step
+                if budget.next_boid < self.boids.len() {
+                    self.step_boid(
+                        budget.next_boid,
+                        budget.step_dt,
+                        rect_max,
+                        attractors,
+                        attraction_strength,
+                    );
+                    budget.next_boid += 1;
+                }
+                if budget.next_boid >= self.boids.len() {
+                    budget.next_boid = 0;
+                    completed_steps += 1;
                 }
             }
-        }
+        });
+        self.frame_budget = Some(budget);
+        completed_steps
+    }
 
-        let dt = ctx.time.delta().as_secs_f32();
-        let mouse_pos = Vec2::new(ctx.mouse.position().x, ctx.mouse.position().y);
-        {
-            tracy_scope!("update_boids");
-            for boid_idx in 0..self.boids.len() {
-                let mut boid = self.boids[boid_idx].borrow_mut(); // Safety: we check the index to avoid borrowing self
-                boid.apply_behavior(boid_idx, &self.boids, mouse_pos, self.is_attracted);
-                boid.update(dt, &mut self.rng);
-                boid.edges(self.rect_max.x, self.rect_max.y);
-            }
+    #[cfg(feature = "bandwidth")]
+    fn step_bytes(&self) -> Option<(usize, usize)> {
+        self.frame_budget
+            .is_none()
+            .then_some((NEIGHBOR_BYTES, WRITTEN_BYTES))
+    }
+
+    fn supports(&self, action: Action) -> bool {
+        matches!(action, Action::AddBoids | Action::RemoveBoids)
+    }
+
+    fn apply_action(&mut self, action: Action) {
+        match action {
+            Action::AddBoids => self.add_boids(),
+            Action::RemoveBoids => self.remove_boids(),
+            _ => {}
         }
-        Ok(())
     }
 
-    fn draw(&mut self, ctx: &mut Context) -> GameResult {
-        tracy_scope!("draw");
-        let mut canvas = graphics::Canvas::from_frame(ctx, Color::WHITE);
+    fn action_state(&self, action: Action) -> Option<String> {
+        match action {
+            Action::AddBoids | Action::RemoveBoids => Some(self.boids.len().to_string()),
+            _ => None,
+        }
+    }
 
-        {
-            tracy_scope!("draw_boids");
-            let boid_mesh = self.make_boid_mesh(ctx)?;
-            for boid_cell in &self.boids {
-                tracy_scope!("draw_boids");
-                boid_cell.borrow().draw(&mut canvas, &boid_mesh)?;
-            }
+    fn step_text(&self) -> String {
+        match self.frame_budget {
+            Some(budget) => format!(
+                ", {:.1} ms budget, {:.0}% into the step",
+                budget.budget.as_secs_f32() * 1000.0,
+                100.0 * budget.next_boid as f32 / self.boids.len().max(1) as f32
+            ),
+            None => String::new(),
         }
+    }
+}
 
-        {
-            tracy_scope!("draw_ui");
-            let fps_text = Text::new(format!("FPS: {:.2}", ctx.time.fps()));
-            canvas.draw(
-                &fps_text,
-                DrawParam::new()
-                    .dest(Vec2::new(10.0, 10.0))
-                    .color(Color::BLACK),
-            );
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use boids_common::scalar;
 
-            let frametime_text = Text::new(format!(
-                "Frame time: {:.2} us",
-                ctx.time.delta().as_micros()
-            ));
-            canvas.draw(
-                &frametime_text,
-                DrawParam::new()
-                    .dest(Vec2::new(10.0, 20.0))
-                    .color(Color::BLACK),
-            );
+    fn boid_ref(position: RealVec2, velocity: RealVec2) -> BoidRef {
+        let boid_cell = RefCell::new(Boid::new(position, velocity));
+
+        #[cfg(not(feature = "no_boxing"))]
+        return Box::new(boid_cell);
+
+        #[cfg(feature = "no_boxing")]
+        return boid_cell;
+    }
 
-            let boid_count_text = Text::new(format!("Boids: {}", self.boids.len()));
-            canvas.draw(
-                &boid_count_text,
-                DrawParam::new()
-                    .dest(Vec2::new(10.0, 50.0))
-                    .color(Color::BLACK),
+    // The rules themselves are tested on the packed boids in boids-common; this checks that the
+    // boxed boids give the same accelerations, with whichever rule variant is built.
+    #[test]
+    fn boxed_rules_match_the_shared_scalar_rules() {
+        let mut rng = seeded_rng(7);
+        let mut boids: Vec<(RealVec2, RealVec2)> = (0..40)
+            .map(|_| {
+                let position = RealVec2::new(rng.gen_range(0.0..150.0), rng.gen_range(0.0..150.0));
+                let velocity =
+                    RealVec2::new(rng.gen_range(-50.0..50.0), rng.gen_range(-50.0..50.0));
+                (position, velocity)
+            })
+            .collect();
+        // A coincident neighbor, which both drop
+        boids[3].0 = boids[1].0;
+        let boxed: Vec<BoidRef> = boids
+            .iter()
+            .map(|&(position, velocity)| boid_ref(position, velocity))
+            .collect();
+        let packed: Vec<scalar::Boid> = boids
+            .iter()
+            .map(|&(position, velocity)| scalar::Boid::new(position, velocity))
+            .collect();
+        for (idx, &(position, velocity)) in boids.iter().enumerate() {
+            let mut probe = Boid::new(position, velocity);
+            probe.apply_behavior(idx, &boxed, &[], 0.0);
+            let shared = packed[idx].calc_acceleration(idx, &packed, &[], 0.0);
+            assert!(
+                probe.acceleration.abs_diff_eq(shared, 1e-3),
+                "boid {idx}: boxed {} against shared {shared}",
+                probe.acceleration
             );
         }
+    }
 
-        canvas.finish(ctx)?;
-
-        tracy_client::frame_mark();
-        Ok(())
+    // A rule that borrowed every cell would trip over the one `step_boid` holds mutably
+    #[test]
+    fn step_leaves_the_stepped_boid_alone() {
+        let rect_max = Vec2::new(1080.0, 800.0);
+        let boids = [
+            (RealVec2::new(100.0, 100.0), RealVec2::X),
+            (RealVec2::new(110.0, 100.0), RealVec2::Y),
+        ];
+        let mut flock = Flock::from_boids(&Config::default(), rect_max, &boids);
+        assert_eq!(flock.step(1, 0.01, to_real(rect_max), &[], 0.0), 1);
+        assert_eq!(flock.num_boids(), 2);
     }
 }
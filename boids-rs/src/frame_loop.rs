@@ -0,0 +1,531 @@
+use std::time::Duration;
+
+use ggez::event::winit_event::TouchPhase;
+use ggez::event::{Axis, Button, EventHandler, GamepadId, MouseButton};
+use ggez::graphics::{self, Color, DrawParam};
+use ggez::{Context, GameResult};
+use glam::Vec2;
+
+use crate::config::Config;
+use crate::util::*;
+
+/// One way of stepping the flock. Everything else in a frame, from the input and the attractors
+/// to the history, the checksum and the overlay, is the same for every implementation and lives
+/// in `MainState`.
+pub trait Simulation {
+    /// Labels the implementation in the help, the energy report and hitch captures.
+    fn name(&self) -> &'static str;
+
+    fn num_boids(&self) -> usize;
+
+    fn position(&self, boid_idx: usize) -> RealVec2;
+
+    /// Position and velocity of every boid, in index order.
+    fn boids(&self) -> impl Iterator<Item = (RealVec2, RealVec2)> + '_;
+
+    /// Runs up to `steps` steps of `dt` each and returns how many of them finished.
+    fn step(
+        &mut self,
+        steps: u32,
+        dt: Real,
+        rect_max: RealVec2,
+        attractors: &[RealVec2],
+        attraction_strength: Real,
+    ) -> u32;
+
+    /// Bytes read and written per boid by one step, `None` while a step does not cover the
+    /// whole flock in one go.
+    #[cfg(feature = "bandwidth")]
+    fn step_bytes(&self) -> Option<(usize, usize)>;
+
+    /// Refills the snapshot that the draw and the selection work from.
+    fn capture(&self, snapshot: &mut SimSnapshot, color: Color) {
+        alloc_free!(
+            "snapshot",
+            snapshot.capture(
+                self.boids()
+                    .map(|(position, velocity)| (to_render(position), to_render(velocity))),
+                color,
+            )
+        );
+    }
+
+    /// Whether this implementation handles `action`, on top of the ones every implementation has.
+    fn supports(&self, _action: Action) -> bool {
+        false
+    }
+
+    fn apply_action(&mut self, _action: Action) {}
+
+    fn action_state(&self, _action: Action) -> Option<String> {
+        None
+    }
+
+    /// Appended to the frame time line of the overlay.
+    fn step_text(&self) -> String {
+        String::new()
+    }
+
+    /// The overlay lines only this implementation has numbers for.
+    fn draw_overlay(&self, _canvas: &mut graphics::Canvas, _palette: &Palette) {}
+}
+
+/// The frame loop around a `Simulation`: input, attractors and the per-frame bookkeeping in
+/// `update`, the instanced flock and the overlay in `draw`.
+pub struct MainState<S> {
+    sim: S,
+    is_attracted: bool,
+    rect_max: Vec2,
+    vsync: bool,
+    idle: IdleThrottle,
+    palette: Palette,
+    instruments: Instruments,
+    paused: bool,
+    gamepad: GamepadInput,
+    touches: TouchInput,
+    attractors: Vec<RealVec2>,
+    attraction_strength: Real,
+    attract_mode: Option<AttractMode>,
+    #[cfg(feature = "audio")]
+    audio: Option<audio::AudioPulse>,
+    show_help: bool,
+    checksum: Option<StateChecksum>,
+    history: Option<FrameHistory>,
+    hitch_watch: Option<HitchWatch>,
+    speed_histogram: Option<SpeedHistogram>,
+    energy: Option<EnergyMeter>,
+    sim_steps: u32,
+    clamp_dt: bool,
+    selection: Selection,
+    boid_mesh: graphics::Mesh,
+    snapshot: SimSnapshot,
+    boid_instances: graphics::InstanceArray,
+    selected_instances: graphics::InstanceArray,
+    faults: Option<FaultReport>,
+}
+
+impl<S: Simulation> MainState<S> {
+    pub fn new(ctx: &Context, config: &Config, rect_max: Vec2, sim: S) -> GameResult<Self> {
+        let mut boid_instances = graphics::InstanceArray::new(ctx, None);
+        let mut snapshot = SimSnapshot::new(config.palette.boid);
+        if config.prefault {
+            boid_instances.resize(ctx, sim.num_boids());
+            snapshot.resize(sim.num_boids());
+        }
+        Ok(MainState {
+            sim,
+            is_attracted: false,
+            rect_max,
+            vsync: config.vsync,
+            idle: IdleThrottle::new(config.idle_fps),
+            palette: config.palette,
+            instruments: Instruments::new(),
+            paused: false,
+            gamepad: GamepadInput::default(),
+            touches: TouchInput::default(),
+            attractors: vec![],
+            attraction_strength: 1.0,
+            attract_mode: config.options.attract_mode.then(AttractMode::default),
+            #[cfg(feature = "audio")]
+            audio: audio::AudioPulse::open().or_else(|| {
+                eprintln!("No usable audio input, running without the audio pulse");
+                None
+            }),
+            show_help: false,
+            checksum: config
+                .checksum
+                .then(|| StateChecksum::new(config.checksum_log)),
+            history: (config.history_minutes > 0.0)
+                .then(|| FrameHistory::new(Duration::from_secs_f32(config.history_minutes * 60.0))),
+            hitch_watch: (config.hitch_factor > 0.0).then(|| HitchWatch::new(config.hitch_factor)),
+            speed_histogram: config.speed_histogram.clone().map(SpeedHistogram::new),
+            energy: config.energy.then(EnergyMeter::open).flatten(),
+            sim_steps: config.sim_steps_per_frame,
+            clamp_dt: config.clamp_dt,
+            selection: Selection::default(),
+            boid_mesh: make_boid_mesh(ctx)?,
+            snapshot,
+            boid_instances,
+            selected_instances: graphics::InstanceArray::new(ctx, None),
+            faults: None,
+        })
+    }
+
+    /// Prints the energy used by this implementation's part of the run.
+    pub fn report_energy(&self) {
+        if let Some(energy) = &self.energy {
+            energy.report(self.sim.name());
+        }
+    }
+
+    pub fn report_page_faults(&mut self, faults: FaultReport) {
+        self.faults = Some(faults);
+    }
+
+    const ACTIONS: &'static [Action] = &[
+        Action::ToggleHelp,
+        Action::ToggleAttraction,
+        Action::TogglePause,
+        Action::ToggleVsync,
+        Action::DumpHistory,
+        Action::ToggleTracking,
+    ];
+
+    fn supports(&self, action: Action) -> bool {
+        Self::ACTIONS.contains(&action) || self.sim.supports(action)
+    }
+
+    fn apply_action(&mut self, ctx: &Context, action: Action) {
+        match action {
+            Action::ToggleHelp => self.show_help = !self.show_help,
+            Action::ToggleAttraction => self.is_attracted = !self.is_attracted,
+            Action::TogglePause => self.paused = !self.paused,
+            Action::ToggleVsync => {
+                self.vsync = !self.vsync;
+                set_vsync(ctx, self.vsync);
+            }
+            Action::DumpHistory => {
+                if let Some(history) = &self.history {
+                    history.dump();
+                }
+            }
+            Action::SwitchImplementation => {
+                // Each implementation reports the energy of its own part of the run
+                self.report_energy();
+                self.sim.apply_action(action);
+                if let Some(energy) = &mut self.energy {
+                    energy.restart();
+                }
+                #[cfg(feature = "alloc_check")]
+                alloc_check::restart();
+                tracy_message!("Switched to the {} implementation", self.sim.name());
+            }
+            Action::ToggleTracking => self.selection.toggle_tracking(),
+            _ => {
+                self.sim.apply_action(action);
+                // The action may have removed selected boids
+                self.selection.truncate(self.sim.num_boids());
+            }
+        }
+        if self.supports(action) {
+            report_action(action, self.action_state(action));
+        }
+    }
+
+    fn action_state(&self, action: Action) -> Option<String> {
+        let on_off = |on: bool| if on { "on" } else { "off" }.to_string();
+        match action {
+            Action::ToggleAttraction => Some(on_off(self.is_attracted)),
+            Action::TogglePause => Some(on_off(self.paused)),
+            Action::ToggleVsync => Some(on_off(self.vsync)),
+            Action::DumpHistory => self
+                .history
+                .as_ref()
+                .map(|history| format!("{} frames", history.len())),
+            Action::ToggleTracking => Some(format!(
+                "{}, {} selected",
+                on_off(self.selection.tracking),
+                self.selection.indices.len()
+            )),
+            Action::ToggleHelp => None,
+            _ => self.sim.action_state(action),
+        }
+    }
+
+    fn boid_color(&self) -> Color {
+        if !self.attractors.is_empty() {
+            self.palette.attracted_boid
+        } else {
+            self.palette.boid
+        }
+    }
+}
+
+impl<S: Simulation> EventHandler for MainState<S> {
+    fn update(&mut self, ctx: &mut Context) -> GameResult {
+        self.idle.wait();
+        tracy_scope!("update");
+        #[cfg(feature = "alloc_check")]
+        alloc_check::frame(self.sim.num_boids());
+        if let Some(history) = &mut self.history {
+            history.record(ctx.time.delta(), self.sim.num_boids());
+        }
+        if let Some(hitch_watch) = &mut self.hitch_watch {
+            if self.idle.is_idle() {
+                hitch_watch.reset();
+            } else if let Some(hitch) = hitch_watch.record(ctx.time.delta()) {
+                hitch.capture(
+                    self.sim.name(),
+                    self.sim.boids().map(|(position, velocity)| {
+                        [position.x, position.y, velocity.x, velocity.y]
+                    }),
+                );
+            }
+        }
+        if let Some(action) = pressed_action(ctx) {
+            self.apply_action(ctx, action);
+        }
+
+        let dt = ctx.time.delta().as_secs_f32();
+        self.gamepad.update(dt, self.rect_max);
+        self.attractors.clear();
+        let camera = self.selection.camera;
+        if !self.touches.points.is_empty() {
+            self.attractors.extend(
+                self.touches
+                    .points
+                    .iter()
+                    .map(|point| to_real(*point + camera)),
+            );
+        } else if self.is_attracted {
+            self.attractors.push(to_real(
+                self.gamepad
+                    .cursor
+                    .unwrap_or(to_logical(ctx, ctx.mouse.position().into()))
+                    + camera,
+            ));
+        }
+        self.attraction_strength = 1.0;
+        if let Some(attract_mode) = &mut self.attract_mode {
+            attract_mode.update(dt);
+            self.palette = attract_mode.palette();
+            if !self.selection.tracking {
+                self.selection.camera = attract_mode.camera(self.rect_max);
+            }
+            if self.attractors.is_empty() {
+                self.attractors
+                    .extend(attract_mode.attractor(self.rect_max).map(to_real));
+                self.attraction_strength = attract_mode.attraction_strength() as Real;
+            }
+        }
+        #[cfg(feature = "audio")]
+        if let (true, Some(audio)) = (self.attractors.is_empty(), &mut self.audio) {
+            let pulse = audio.update(dt);
+            if pulse > 0.0 {
+                self.attractors.push(to_real(self.rect_max / 2.0));
+                self.attraction_strength = pulse as Real;
+            }
+        }
+        let sim_dt = checked_dt(dt as Real / self.sim_steps as Real, self.clamp_dt);
+        let mut completed_steps = 0;
+        if !self.paused {
+            tracy_scope!("update_boids");
+            #[cfg(feature = "bandwidth")]
+            let step_start = std::time::Instant::now();
+            completed_steps = self.sim.step(
+                self.sim_steps,
+                sim_dt,
+                to_real(self.rect_max),
+                &self.attractors,
+                self.attraction_strength,
+            );
+            #[cfg(feature = "bandwidth")]
+            if let Some((neighbor_bytes, written_bytes)) = self.sim.step_bytes() {
+                self.instruments.bandwidth.take_frame(
+                    self.sim.num_boids(),
+                    neighbor_bytes,
+                    written_bytes,
+                    step_start.elapsed() / self.sim_steps,
+                );
+            }
+        }
+
+        let sim = &self.sim;
+        self.selection
+            .follow(|boid_idx| to_render(sim.position(boid_idx)), self.rect_max);
+
+        if let Some(energy) = &mut self.energy {
+            energy.frame_done(completed_steps);
+        }
+
+        if let Some(checksum) = &mut self.checksum {
+            if completed_steps > 0 {
+                checksum.update(self.sim.boids().flat_map(|(position, velocity)| {
+                    [position.x, position.y, velocity.x, velocity.y]
+                }));
+            }
+        }
+
+        if let Some(speed_histogram) = &mut self.speed_histogram {
+            if speed_histogram.is_due() {
+                speed_histogram.record(
+                    self.sim.name(),
+                    self.sim.boids().map(|(_, velocity)| velocity),
+                );
+            }
+        }
+
+        self.instruments.take_frame();
+
+        let color = self.boid_color();
+        self.sim.capture(&mut self.snapshot, color);
+        Ok(())
+    }
+
+    fn draw(&mut self, ctx: &mut Context) -> GameResult {
+        tracy_scope!("draw");
+        let mut canvas = graphics::Canvas::from_frame(ctx, self.palette.background);
+        canvas.set_screen_coordinates(graphics::Rect::new(
+            self.selection.camera.x,
+            self.selection.camera.y,
+            self.rect_max.x,
+            self.rect_max.y,
+        ));
+
+        {
+            tracy_scope!("draw_boids");
+            alloc_free!(
+                "draw_boids",
+                self.boid_instances.set(self.snapshot.draw_params())
+            );
+            canvas.draw_instanced_mesh(
+                self.boid_mesh.clone(),
+                &self.boid_instances,
+                DrawParam::new().color(self.snapshot.color),
+            );
+            self.selected_instances.set(
+                self.selection
+                    .indices
+                    .iter()
+                    .map(|&boid_idx| self.snapshot.draw_param(boid_idx)),
+            );
+            canvas.draw_instanced_mesh(
+                self.boid_mesh.clone(),
+                &self.selected_instances,
+                DrawParam::new().color(self.palette.text),
+            );
+        }
+
+        // The cursor, the drag box and the overlay stay put while the camera moves
+        canvas.set_screen_coordinates(graphics::Rect::new(
+            0.0,
+            0.0,
+            self.rect_max.x,
+            self.rect_max.y,
+        ));
+        self.selection
+            .draw_drag(ctx, &mut canvas, self.palette.text)?;
+        if let Some(cursor) = self.gamepad.cursor {
+            draw_gamepad_cursor(ctx, &mut canvas, cursor, self.palette.text)?;
+        }
+
+        {
+            tracy_scope!("draw_ui");
+            let step_text = self.sim.step_text();
+            Overlay {
+                sim_steps: self.sim_steps,
+                step_text: &step_text,
+                vsync: self.vsync,
+                paused: self.paused,
+                num_boids: self.sim.num_boids(),
+                checksum: self.checksum.as_ref(),
+                instruments: &self.instruments,
+                energy: self.energy.as_ref(),
+                help: self.show_help.then(|| {
+                    help_text(
+                        |action| self.supports(action),
+                        |action| self.action_state(action),
+                    )
+                }),
+            }
+            .draw(ctx, &mut canvas, &self.palette, self.rect_max);
+            self.sim.draw_overlay(&mut canvas, &self.palette);
+        }
+
+        canvas.finish(ctx)?;
+        if let Some(faults) = &mut self.faults {
+            faults.frame_done();
+        }
+
+        perf_instrument::frame_mark();
+        Ok(())
+    }
+
+    fn resize_event(&mut self, ctx: &mut Context, width: f32, height: f32) -> GameResult {
+        self.idle.set_window_size(width, height);
+        set_vsync(ctx, self.vsync);
+        Ok(())
+    }
+
+    fn focus_event(&mut self, _ctx: &mut Context, gained: bool) -> GameResult {
+        self.idle.set_focused(gained);
+        Ok(())
+    }
+
+    fn quit_event(&mut self, _ctx: &mut Context) -> GameResult<bool> {
+        self.report_energy();
+        if let Some(speed_histogram) = &self.speed_histogram {
+            speed_histogram.write();
+        }
+        Ok(false)
+    }
+
+    fn mouse_button_down_event(
+        &mut self,
+        ctx: &mut Context,
+        button: MouseButton,
+        x: f32,
+        y: f32,
+    ) -> GameResult {
+        if button == MouseButton::Left {
+            self.selection.begin_drag(to_logical(ctx, Vec2::new(x, y)));
+        }
+        Ok(())
+    }
+
+    fn mouse_button_up_event(
+        &mut self,
+        _ctx: &mut Context,
+        button: MouseButton,
+        _x: f32,
+        _y: f32,
+    ) -> GameResult {
+        if button == MouseButton::Left {
+            self.selection
+                .finish_drag(self.snapshot.positions.iter().copied());
+        }
+        Ok(())
+    }
+
+    fn mouse_motion_event(
+        &mut self,
+        ctx: &mut Context,
+        x: f32,
+        y: f32,
+        _dx: f32,
+        _dy: f32,
+    ) -> GameResult {
+        self.gamepad.cursor = None;
+        self.selection.drag_to(to_logical(ctx, Vec2::new(x, y)));
+        Ok(())
+    }
+
+    fn touch_event(&mut self, ctx: &mut Context, phase: TouchPhase, x: f64, y: f64) -> GameResult {
+        self.touches
+            .handle(phase, to_logical(ctx, Vec2::new(x as f32, y as f32)));
+        Ok(())
+    }
+
+    fn gamepad_button_down_event(
+        &mut self,
+        ctx: &mut Context,
+        btn: Button,
+        _id: GamepadId,
+    ) -> GameResult {
+        if let Some(action) = self.gamepad.button_action(btn) {
+            self.apply_action(ctx, action);
+        }
+        Ok(())
+    }
+
+    fn gamepad_axis_event(
+        &mut self,
+        _ctx: &mut Context,
+        axis: Axis,
+        value: f32,
+        _id: GamepadId,
+    ) -> GameResult {
+        self.gamepad.axis_changed(axis, value);
+        Ok(())
+    }
+}
@@ -0,0 +1,156 @@
+use ggez::graphics::{self, Color};
+use glam::Vec2;
+
+use crate::config::Config;
+use crate::frame_loop::Simulation;
+use crate::util::*;
+use crate::{default_impl, multithreaded_impl};
+
+// Every method of the two flocks has the same shape, the call goes to the current one
+macro_rules! on_current {
+    ($flock:expr, $current:ident => $call:expr) => {
+        match $flock {
+            Flock::Scalar($current) => $call,
+            Flock::Threaded($current) => $call,
+        }
+    };
+}
+
+// Boxed, the two flocks differ a lot in size and only one is alive at a time
+enum Flock {
+    Scalar(Box<default_impl::Flock>),
+    Threaded(Box<multithreaded_impl::Flock>),
+}
+
+/// Both implementations behind one frame loop. The switch converts the flock between the boxed
+/// per-boid layout and the packed double buffer and carries on with the other update. Everything
+/// around the step stays put, so the frame time is the only thing that visibly changes. Starts on
+/// the threaded one with the `threaded` feature.
+pub struct HotSwitch {
+    config: Config,
+    rect_max: Vec2,
+    flock: Flock,
+}
+
+impl HotSwitch {
+    pub fn new(config: &Config, rect_max: Vec2) -> Self {
+        #[cfg(not(feature = "threaded"))]
+        let flock = Flock::Scalar(Box::new(default_impl::Flock::new(config, rect_max)));
+        #[cfg(feature = "threaded")]
+        let flock = Flock::Threaded(Box::new(multithreaded_impl::Flock::new(config, rect_max)));
+        HotSwitch {
+            config: config.clone(),
+            rect_max,
+            flock,
+        }
+    }
+
+    /// Converts the flock to the other layout, everything else about the run carries on.
+    fn switch(&mut self) {
+        let boids: Vec<_> = self.boids().collect();
+        self.flock = match self.flock {
+            Flock::Scalar(_) => Flock::Threaded(Box::new(multithreaded_impl::Flock::from_boids(
+                &self.config,
+                &boids,
+            ))),
+            Flock::Threaded(_) => Flock::Scalar(Box::new(default_impl::Flock::from_boids(
+                &self.config,
+                self.rect_max,
+                &boids,
+            ))),
+        };
+    }
+}
+
+impl Simulation for HotSwitch {
+    fn name(&self) -> &'static str {
+        on_current!(&self.flock, flock => flock.name())
+    }
+
+    fn num_boids(&self) -> usize {
+        on_current!(&self.flock, flock => flock.num_boids())
+    }
+
+    fn position(&self, boid_idx: usize) -> RealVec2 {
+        on_current!(&self.flock, flock => flock.position(boid_idx))
+    }
+
+    fn boids(&self) -> impl Iterator<Item = (RealVec2, RealVec2)> + '_ {
+        // The two iterators differ in type, only one of the halves has any boids
+        let (scalar, threaded) = match &self.flock {
+            Flock::Scalar(flock) => (Some(flock.boids()), None),
+            Flock::Threaded(flock) => (None, Some(flock.boids())),
+        };
+        scalar
+            .into_iter()
+            .flatten()
+            .chain(threaded.into_iter().flatten())
+    }
+
+    fn step(
+        &mut self,
+        steps: u32,
+        dt: Real,
+        rect_max: RealVec2,
+        attractors: &[RealVec2],
+        attraction_strength: Real,
+    ) -> u32 {
+        on_current!(&mut self.flock, flock => {
+            flock.step(steps, dt, rect_max, attractors, attraction_strength)
+        })
+    }
+
+    #[cfg(feature = "bandwidth")]
+    fn step_bytes(&self) -> Option<(usize, usize)> {
+        on_current!(&self.flock, flock => flock.step_bytes())
+    }
+
+    fn capture(&self, snapshot: &mut SimSnapshot, color: Color) {
+        on_current!(&self.flock, flock => flock.capture(snapshot, color))
+    }
+
+    fn supports(&self, action: Action) -> bool {
+        action == Action::SwitchImplementation
+            || on_current!(&self.flock, flock => flock.supports(action))
+    }
+
+    fn apply_action(&mut self, action: Action) {
+        match action {
+            Action::SwitchImplementation => self.switch(),
+            _ => on_current!(&mut self.flock, flock => flock.apply_action(action)),
+        }
+    }
+
+    fn action_state(&self, action: Action) -> Option<String> {
+        match action {
+            Action::SwitchImplementation => Some(self.name().to_string()),
+            _ => on_current!(&self.flock, flock => flock.action_state(action)),
+        }
+    }
+
+    fn step_text(&self) -> String {
+        on_current!(&self.flock, flock => flock.step_text())
+    }
+
+    fn draw_overlay(&self, canvas: &mut graphics::Canvas, palette: &Palette) {
+        on_current!(&self.flock, flock => flock.draw_overlay(canvas, palette))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn switching_carries_the_flock_over() {
+        let mut hot_switch = HotSwitch::new(&Config::default(), Vec2::new(1080.0, 800.0));
+        let boids: Vec<_> = hot_switch.boids().collect();
+        let name = hot_switch.name();
+        hot_switch.apply_action(Action::SwitchImplementation);
+        assert_ne!(hot_switch.name(), name);
+        assert!(hot_switch.boids().eq(boids.iter().copied()));
+        hot_switch.apply_action(Action::SwitchImplementation);
+        assert_eq!(hot_switch.name(), name);
+        assert!(hot_switch.boids().eq(boids.iter().copied()));
+    }
+}
@@ -0,0 +1,13 @@
+//! The flock implementations of the boids binary and the frame loop around them. They are a
+//! library so that boids-bench can step them without a window.
+
+// The rule loops index the boid slices on purpose so that the self-skip reads the same everywhere.
+#![allow(clippy::needless_range_loop)]
+
+pub mod config;
+pub mod default_impl;
+pub mod frame_loop;
+pub mod hot_switch;
+pub mod multithreaded_impl;
+#[macro_use]
+pub mod util;
@@ -1,38 +1,58 @@
+use boids_rs::frame_loop::MainState;
+use boids_rs::hot_switch::HotSwitch;
+use boids_rs::{config, multithreaded_impl, util};
 use ggez::event::{self};
 use ggez::{ContextBuilder, GameResult};
 use glam::Vec2;
-use std::env;
 
-mod default_impl;
-mod multithreaded_impl;
-#[macro_use]
-mod util;
-
-#[cfg(not(feature = "threaded"))]
-type MainState = default_impl::MainState;
-
-#[cfg(feature = "threaded")]
-type MainState = multithreaded_impl::MainState;
+#[cfg(feature = "alloc_check")]
+#[global_allocator]
+static ALLOCATOR: util::alloc_check::CountingAlloc = util::alloc_check::CountingAlloc;
 
 fn main() -> GameResult {
-    tracy_client::Client::start();
-
-    let num_boids: u16 = env::args()
-        .nth(1)
-        .and_then(|n| n.parse::<u16>().ok())
-        .unwrap_or(100);
+    perf_instrument::start();
 
+    let config = config::Config::from_args();
+    util::report_extra_work(config.extra_work);
     let dim_x = 1080.0;
     let dim_y = 800.0;
+    let num_threads = if config.options.auto_threads {
+        multithreaded_impl::tune_thread_count(&config, Vec2::new(dim_x, dim_y))
+    } else {
+        0
+    };
+    if config.thread_priority || num_threads > 0 {
+        util::configure_threads(config.thread_priority, num_threads);
+    }
+
+    let mut faults = if config.page_faults {
+        util::FaultReport::start()
+    } else {
+        None
+    };
+
     let (ctx, event_loop) = ContextBuilder::new("boids", "Author")
         .window_setup(
             ggez::conf::WindowSetup::default()
                 .title("Boids")
-                .vsync(false),
+                .vsync(config.vsync),
         )
-        .window_mode(ggez::conf::WindowMode::default().dimensions(dim_x, dim_y))
+        // Sized in logical pixels, so the demo covers the same share of a HiDPI display or a 4K
+        // projector as it does of a plain monitor. The states draw in the same logical space.
+        .window_mode(ggez::conf::WindowMode {
+            logical_size: Some(ggez::winit::dpi::LogicalSize::new(dim_x, dim_y)),
+            ..ggez::conf::WindowMode::default().resize_on_scale_factor_change(true)
+        })
         .build()?;
+    if let Some(faults) = &mut faults {
+        faults.phase("window setup");
+    }
 
-    let state = MainState::new(num_boids, Vec2::new(dim_x, dim_y))?;
+    let rect_max = Vec2::new(dim_x, dim_y);
+    let mut state = MainState::new(&ctx, &config, rect_max, HotSwitch::new(&config, rect_max))?;
+    if let Some(mut faults) = faults {
+        faults.phase("simulation setup");
+        state.report_page_faults(faults);
+    }
     event::run(ctx, event_loop, state)
 }
@@ -1,383 +1,658 @@
 use std::cell::UnsafeCell;
 use std::num::NonZero;
+use std::time::Duration;
 
-use ggez::event::EventHandler;
-use ggez::graphics::{self, Color, DrawParam, Text};
-use ggez::{Context, GameResult};
+use boids_common::scalar::Boid;
+use ggez::graphics::{self, Color};
+#[cfg(any(feature = "flock_stats", feature = "pool_stats"))]
+use ggez::graphics::{DrawParam, Text};
 use glam::Vec2;
-use rand::{Rng, SeedableRng};
+use rand::Rng;
 use rayon::prelude::*;
 
+use crate::config::Config;
+use crate::frame_loop::Simulation;
 use crate::util::*;
 
-#[derive(Debug, Clone, Copy, Default)]
-struct Boid {
-    position: Vec2,
-    velocity: Vec2,
+struct BoidsDoubleBuffer {
+    boids: [UnsafeCell<Vec<Boid>>; 2],
+    current_idx: usize,
 }
 
-impl Boid {
-    fn new(position: Vec2, velocity: Vec2) -> Self {
-        Boid { position, velocity }
+impl BoidsDoubleBuffer {
+    fn new(active_boids: Vec<Boid>) -> Self {
+        let len = active_boids.len();
+        BoidsDoubleBuffer {
+            boids: [
+                UnsafeCell::new(active_boids),
+                UnsafeCell::new(vec![Boid::default(); len]),
+            ],
+            current_idx: 0,
+        }
     }
 
-    #[inline(always)]
-    fn is_close_enough(&self, other: &Boid, max_dist: f32) -> bool {
-        let distance = self.position.distance_squared(other.position);
-        distance < (max_dist * max_dist) && distance > 0.0
+    // Both buffers come out of `new` written by the main thread. Collecting fresh ones on the pool,
+    // in the same chunks as the update, moves each page's first touch to a thread that owns it.
+    fn prefault(&mut self) {
+        for buffer in &mut self.boids {
+            let buffer = buffer.get_mut();
+            *buffer = buffer.par_iter().with_min_len(8).copied().collect();
+        }
     }
 
-    #[inline(never)]
-    fn alignment(&self, boids: &[Boid], self_idx: usize) -> Vec2 {
-        let mut alignment = Vec2::ZERO;
-        let mut total = 0;
+    /// One simulation step into the next buffer, then swaps. `par_chunk_len` picks the
+    /// `par_chunks_mut` scheduling over the per-index one.
+    fn step(
+        &mut self,
+        par_chunk_len: Option<usize>,
+        attractors: &[RealVec2],
+        attraction_strength: Real,
+        dt: Real,
+        rect_max: RealVec2,
+        extra_rounds: u32,
+    ) {
+        if let Some(chunk_len) = par_chunk_len {
+            // Each task owns a contiguous slice of the next state, handed out safely by
+            // rayon, instead of indexing into the UnsafeCell buffer from every iteration
+            let (current_boids, next_boids) = self.split_mut();
+            next_boids
+                .par_chunks_mut(chunk_len)
+                .enumerate()
+                .for_each(|(chunk_idx, next_chunk)| {
+                    tracy_scope!("update_boids_thread");
+                    pool_task!();
+                    for (offset, next_boid) in next_chunk.iter_mut().enumerate() {
+                        let boid_idx = chunk_idx * chunk_len + offset;
+                        let boid = &current_boids[boid_idx];
+                        let acc = boid.calc_acceleration(
+                            boid_idx,
+                            current_boids,
+                            attractors,
+                            attraction_strength,
+                        );
+                        extra_work(boid_idx, extra_rounds);
+                        next_boid.update(dt, boid, acc);
+                        next_boid.edges(rect_max.x, rect_max.y);
+                    }
+                });
+        }
+        #[cfg(not(feature = "no_false_sharing"))]
+        if par_chunk_len.is_none() {
+            let buffer = &*self;
+            let boids_len = buffer.get_current_boids().len();
+            // Looked up once: it reads the cgroup limits and allocates on every call
+            static CORE_COUNT: std::sync::OnceLock<usize> = std::sync::OnceLock::new();
+            let core_count = *CORE_COUNT.get_or_init(|| {
+                std::thread::available_parallelism()
+                    .unwrap_or(NonZero::new(1).unwrap())
+                    .into()
+            });
+            (0..core_count)
+                .into_par_iter()
+                .with_min_len(1)
+                .with_max_len(1)
+                .for_each(|core_idx| {
+                    tracy_scope!("update_boids_thread");
+                    pool_task!();
+                    // Strided so the boids past the last full round land on the first cores
+                    for boid_idx in (core_idx..boids_len).step_by(core_count) {
+                        let current_boids = buffer.get_current_boids();
+                        let next_boids = buffer.get_next_boids();
+                        let boid = &current_boids[boid_idx];
+                        let acc = boid.calc_acceleration(
+                            boid_idx,
+                            current_boids,
+                            attractors,
+                            attraction_strength,
+                        );
+                        let next_boid = &mut next_boids[boid_idx];
+                        std::hint::black_box(next_boid.position + next_boid.velocity);
+                        extra_work(boid_idx, extra_rounds);
+                        next_boid.update(dt, boid, acc);
+                        next_boid.edges(rect_max.x, rect_max.y);
+                    }
+                });
+        }
+        #[cfg(feature = "no_false_sharing")]
+        if par_chunk_len.is_none() {
+            let buffer = &*self;
+            (0..buffer.get_current_boids().len())
+                .into_par_iter()
+                .with_min_len(8)
+                .for_each(|boid_idx| {
+                    tracy_scope!("update_boids_thread");
+                    pool_task!();
+                    let current_boids = buffer.get_current_boids();
+                    let next_boids = buffer.get_next_boids();
+                    let boid = &current_boids[boid_idx];
+                    let acc = boid.calc_acceleration(
+                        boid_idx,
+                        current_boids,
+                        attractors,
+                        attraction_strength,
+                    );
+                    extra_work(boid_idx, extra_rounds);
+                    next_boids[boid_idx].update(dt, boid, acc);
+                    next_boids[boid_idx].edges(rect_max.x, rect_max.y);
+                });
+        }
+        self.swap();
+    }
 
-        for other_idx in 0..boids.len() {
-            if other_idx == self_idx {
-                continue;
-            }
+    fn get_current_boids(&self) -> &[Boid] {
+        unsafe { &*self.boids[self.current_idx].get() }
+    }
 
-            let other = &boids[other_idx];
-            if self.is_close_enough(&other, PERCEPTION) {
-                alignment += other.velocity;
-                total += 1;
-            }
-        }
+    #[allow(clippy::mut_from_ref)]
+    fn get_next_boids(&self) -> &mut [Boid] {
+        unsafe { &mut *self.boids[self.current_idx ^ 1].get() }
+    }
 
-        if total > 0 {
-            alignment /= total as f32;
-            alignment = alignment.normalize() * MAX_SPEED;
-            alignment -= self.velocity;
-            alignment = alignment.clamp_length_max(MAX_FORCE);
+    // With `&mut self` both buffers can be borrowed safely, no UnsafeCell access needed
+    fn split_mut(&mut self) -> (&[Boid], &mut [Boid]) {
+        let [first, second] = &mut self.boids;
+        let (first, second) = (first.get_mut(), second.get_mut());
+        if self.current_idx == 0 {
+            (first, second)
+        } else {
+            (second, first)
         }
-        alignment
     }
 
-    #[inline(never)]
-    fn cohesion(&self, boids: &[Boid], self_idx: usize) -> Vec2 {
-        let mut cohesion = Vec2::ZERO;
-        let mut total = 0;
+    fn swap(&mut self) {
+        self.current_idx ^= 1;
+    }
+}
 
-        for other_idx in 0..boids.len() {
-            if other_idx == self_idx {
-                continue;
-            }
+unsafe impl Sync for BoidsDoubleBuffer {}
 
-            let other = &boids[other_idx];
-            if self.is_close_enough(&other, PERCEPTION) {
-                cohesion += other.position;
-                total += 1;
-            }
-        }
+/// Per-frame flock statistics for the `flock_stats` overlay, reduced over the packed buffer.
+mod flock_stats {
+    // Compiled either way, the tests check the reductions without the overlay
+    #![cfg_attr(not(feature = "flock_stats"), allow(dead_code))]
 
-        if total > 0 {
-            cohesion /= total as f32;
-            cohesion -= self.position;
-            cohesion = cohesion.normalize() * MAX_SPEED;
-            cohesion -= self.velocity;
-            cohesion = cohesion.clamp_length_max(MAX_FORCE);
-        }
+    use std::sync::atomic::Ordering;
 
-        cohesion
-    }
+    use rayon::prelude::*;
 
-    #[inline(never)]
-    fn separation(&self, boids: &[Boid], self_idx: usize) -> Vec2 {
-        let mut separation = Vec2::ZERO;
-        let mut total_separation = 0;
+    use super::Boid;
+    use crate::util::{Real, RealVec2};
 
-        for other_idx in 0..boids.len() {
-            if other_idx == self_idx {
-                continue;
-            }
+    #[cfg(not(feature = "f64"))]
+    type AtomicReal = std::sync::atomic::AtomicU32;
+    #[cfg(feature = "f64")]
+    type AtomicReal = std::sync::atomic::AtomicU64;
 
-            let other = &boids[other_idx];
-            let distance = self.position.distance(other.position);
+    /// The sums behind the flock statistics: positions for the centroid, unit headings for the
+    /// polarization.
+    #[derive(Debug, Clone, Copy, Default, PartialEq)]
+    pub struct FlockSums {
+        pub position: RealVec2,
+        pub heading: RealVec2,
+    }
 
-            if distance < SEPARATION && distance > 0.0 {
-                let diff = (self.position - other.position).normalize() / distance;
-                separation += diff;
-                total_separation += 1;
+    impl FlockSums {
+        fn of(boid: &Boid) -> Self {
+            FlockSums {
+                position: boid.position,
+                heading: boid.velocity.normalize_or_zero(),
             }
         }
 
-        if total_separation > 0 {
-            separation /= total_separation as f32;
-            separation = separation.normalize() * MAX_SPEED;
-            separation -= self.velocity;
-            separation = separation.clamp_length_max(MAX_FORCE);
+        fn add(self, other: FlockSums) -> Self {
+            FlockSums {
+                position: self.position + other.position,
+                heading: self.heading + other.heading,
+            }
         }
-
-        separation
     }
 
-    #[inline(never)]
-    fn calc_acceleration(
-        &self,
-        self_idx: usize,
-        boids: &[Boid],
-        mouse_pos: Vec2,
-        is_attracted: bool,
-    ) -> Vec2 {
-        let alignment = self.alignment(boids, self_idx);
-        let cohesion = self.cohesion(boids, self_idx);
-        let separation = self.separation(boids, self_idx);
-
-        let mut acceleration = alignment;
-        acceleration += cohesion;
-        acceleration += separation;
-
-        if is_attracted {
-            let attraction = (mouse_pos - self.position).normalize() * MAX_SPEED;
-            acceleration += attraction;
+    /// Boids summed serially at the leaves of the reduction tree.
+    const STATS_LEAF_LEN: usize = 256;
+
+    /// Splits at fixed midpoints down to `STATS_LEAF_LEN` boids, so the shape of the tree, and with it
+    /// the order of every addition, depends on the boid count alone. Rayon only picks the thread that
+    /// runs each branch, and the sums are bit-identical on any number of threads.
+    pub fn tree_sums(boids: &[Boid]) -> FlockSums {
+        if boids.len() <= STATS_LEAF_LEN {
+            return boids
+                .iter()
+                .map(FlockSums::of)
+                .fold(FlockSums::default(), FlockSums::add);
         }
-        assert!(acceleration.is_finite());
-        acceleration
+        let (left, right) = boids.split_at(boids.len() / 2);
+        let (left, right) = rayon::join(|| tree_sums(left), || tree_sums(right));
+        left.add(right)
     }
 
-    fn update(&mut self, dt: f32, source: &Boid, acceleration: Vec2) {
-        self.position = source.position;
-        self.velocity = source.velocity;
-
-        let this_frame_acceleration = std::hint::black_box(acceleration * dt);
-        #[cfg(feature = "static_update")]
-        let this_frame_acceleration = Vec2::ZERO;
-
-        self.velocity += this_frame_acceleration;
-        assert!(self.velocity.is_finite());
-
-        let this_frame_velocity = std::hint::black_box(self.velocity * dt);
-        #[cfg(feature = "static_update")]
-        let this_frame_velocity = Vec2::ZERO;
+    /// The naive version, kept for comparison: each rayon split sums its own run and adds it into
+    /// shared atomics. The runs follow work stealing and the adds land in whatever order the threads
+    /// finish, so the low bits change with the thread count and from frame to frame.
+    pub fn atomic_sums(boids: &[Boid]) -> FlockSums {
+        let sums: [AtomicReal; 4] = Default::default();
+        boids
+            .par_iter()
+            .fold(FlockSums::default, |partial, boid| {
+                partial.add(FlockSums::of(boid))
+            })
+            .for_each(|partial| {
+                let values = [
+                    partial.position.x,
+                    partial.position.y,
+                    partial.heading.x,
+                    partial.heading.y,
+                ];
+                for (sum, value) in sums.iter().zip(values) {
+                    let _ = sum.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| {
+                        Some((Real::from_bits(bits) + value).to_bits())
+                    });
+                }
+            });
+        let [position_x, position_y, heading_x, heading_y] =
+            sums.map(|sum| Real::from_bits(sum.into_inner()));
+        FlockSums {
+            position: RealVec2::new(position_x, position_y),
+            heading: RealVec2::new(heading_x, heading_y),
+        }
+    }
 
-        self.position += this_frame_velocity;
-        assert!(self.position.is_finite());
+    /// Centroid, not corrected for the wrapping edges, and polarization: the length of the mean
+    /// heading, 1 when every boid flies the same way and near 0 for a disordered flock.
+    #[derive(Debug, Clone, Copy, Default)]
+    pub struct FlockStats {
+        centroid: RealVec2,
+        polarization: Real,
     }
 
-    fn edges(&mut self, screen_width: f32, screen_height: f32) {
-        if self.position.x > screen_width {
-            self.position.x = 0.0;
-        } else if self.position.x < 0.0 {
-            self.position.x = screen_width;
+    impl FlockStats {
+        pub fn from_sums(sums: FlockSums, num_boids: usize) -> Self {
+            if num_boids == 0 {
+                return FlockStats::default();
+            }
+            let num_boids = num_boids as Real;
+            FlockStats {
+                centroid: sums.position / num_boids,
+                polarization: (sums.heading / num_boids).length(),
+            }
+        }
+
+        /// Both reductions of the same flock, tree first.
+        pub fn measure(boids: &[Boid]) -> [FlockStats; 2] {
+            [tree_sums(boids), atomic_sums(boids)].map(|sums| Self::from_sums(sums, boids.len()))
         }
 
-        if self.position.y > screen_height {
-            self.position.y = 0.0;
-        } else if self.position.y < 0.0 {
-            self.position.y = screen_height;
+        pub fn text(stats: [FlockStats; 2]) -> String {
+            let [tree, atomic] = stats;
+            format!(
+                "Centroid: ({:.1}, {:.1}), polarization: {:.4}\nPolarization bits: tree {:x}, atomic {:x}",
+                tree.centroid.x,
+                tree.centroid.y,
+                tree.polarization,
+                tree.polarization.to_bits(),
+                atomic.polarization.to_bits()
+            )
         }
     }
+}
 
-    fn draw(&self, canvas: &mut graphics::Canvas, boid_mesh: &graphics::Mesh) -> GameResult {
-        let angle = self.velocity.y.atan2(self.velocity.x);
-        canvas.draw(
-            boid_mesh,
-            graphics::DrawParam::new()
-                .dest(self.position)
-                .rotation(angle),
+// Same granularity as the `with_min_len(8)` of the per-boid par_iter
+const DEFAULT_PAR_CHUNK_LEN: usize = 8;
+
+/// Every boid reads every other boid once per rule, from a packed buffer.
+#[cfg(feature = "bandwidth")]
+const NEIGHBOR_BYTES: usize = 3 * std::mem::size_of::<Boid>();
+#[cfg(feature = "bandwidth")]
+const WRITTEN_BYTES: usize = std::mem::size_of::<Boid>();
+
+/// `--auto-threads`: times a few steps of the starting flock on pools of 1, 2, 4... threads up to
+/// every logical CPU and returns the fastest size. On hybrid P/E-core laptops using all of them
+/// is often slower, and where the best value lies differs per machine.
+pub fn tune_thread_count(config: &Config, rect_max: Vec2) -> usize {
+    const WARMUP_STEPS: usize = 2;
+    const TIMED_STEPS: usize = 8;
+    let available = std::thread::available_parallelism().map_or(1, NonZero::get);
+    let mut candidates: Vec<usize> = std::iter::successors(Some(1), |count| Some(count * 2))
+        .take_while(|&count| count < available)
+        .collect();
+    candidates.push(available);
+
+    let initial_boids = Flock::initial_boids(config, rect_max);
+    let rect_max = to_real(rect_max);
+    let dt = 1.0 / 60.0;
+    let mut best = (available, Duration::MAX);
+    for num_threads in candidates {
+        let pool = match rayon::ThreadPoolBuilder::new()
+            .num_threads(num_threads)
+            .build()
+        {
+            Ok(pool) => pool,
+            Err(err) => {
+                eprintln!("Could not build a pool of {num_threads} threads: {err}");
+                continue;
+            }
+        };
+        let step_time = pool.install(|| {
+            let mut boids = BoidsDoubleBuffer::new(initial_boids.clone());
+            for _ in 0..WARMUP_STEPS {
+                boids.step(
+                    config.options.par_chunks,
+                    &[],
+                    1.0,
+                    dt,
+                    rect_max,
+                    config.extra_work,
+                );
+            }
+            let start = std::time::Instant::now();
+            for _ in 0..TIMED_STEPS {
+                boids.step(
+                    config.options.par_chunks,
+                    &[],
+                    1.0,
+                    dt,
+                    rect_max,
+                    config.extra_work,
+                );
+            }
+            start.elapsed() / TIMED_STEPS as u32
+        });
+        eprintln!(
+            "{num_threads} threads: {:.3} ms per step",
+            step_time.as_secs_f64() * 1000.0
         );
-        Ok(())
+        if step_time < best.1 {
+            best = (num_threads, step_time);
+        }
     }
+    eprintln!("Using {} threads", best.0);
+    best.0
 }
 
-struct BoidsDoubleBuffer {
-    boids: [UnsafeCell<Vec<Boid>>; 2],
-    current_idx: usize,
+/// The packed flock in a double buffer, stepped on the rayon pool.
+pub struct Flock {
+    boids: BoidsDoubleBuffer,
+    par_chunks: bool,
+    par_chunk_len: usize,
+    extra_work: u32,
+    #[cfg(feature = "pool_stats")]
+    pool_stats: pool_stats::Stats,
+    #[cfg(feature = "flock_stats")]
+    flock_stats: [flock_stats::FlockStats; 2],
 }
 
-impl BoidsDoubleBuffer {
-    fn new(active_boids: Vec<Boid>) -> Self {
-        let len = active_boids.len();
-        BoidsDoubleBuffer {
-            boids: [
-                UnsafeCell::new(active_boids),
-                UnsafeCell::new(vec![Boid::default(); len]),
-            ],
-            current_idx: 0,
-        }
+impl Flock {
+    // Without the `threaded` feature the run starts on the scalar flock and only switches here
+    #[cfg_attr(not(feature = "threaded"), allow(dead_code))]
+    pub fn new(config: &Config, rect_max: Vec2) -> Self {
+        Self::with_boids(config, Self::initial_boids(config, rect_max))
     }
 
-    fn get_current_boids(&self) -> &[Boid] {
-        unsafe { &*self.boids[self.current_idx].get() }
+    /// Picks up the flock another implementation left behind.
+    pub fn from_boids(config: &Config, boids: &[(RealVec2, RealVec2)]) -> Self {
+        Self::with_boids(
+            config,
+            boids
+                .iter()
+                .map(|&(position, velocity)| Boid::new(position, velocity))
+                .collect(),
+        )
     }
 
-    fn get_next_boids(&self) -> &mut [Boid] {
-        unsafe { &mut *self.boids[self.current_idx ^ 1].get() }
+    fn with_boids(config: &Config, boids: Vec<Boid>) -> Self {
+        let mut boids = BoidsDoubleBuffer::new(boids);
+        if config.prefault {
+            rayon::broadcast(|_| ());
+            boids.prefault();
+        }
+        Flock {
+            boids,
+            par_chunks: config.options.par_chunks.is_some(),
+            par_chunk_len: config.options.par_chunks.unwrap_or(DEFAULT_PAR_CHUNK_LEN),
+            extra_work: config.extra_work,
+            #[cfg(feature = "pool_stats")]
+            pool_stats: pool_stats::Stats::default(),
+            #[cfg(feature = "flock_stats")]
+            flock_stats: Default::default(),
+        }
     }
 
-    fn swap(&mut self) {
-        self.current_idx ^= 1;
+    fn initial_boids(config: &Config, rect_max: Vec2) -> Vec<Boid> {
+        let mut rng = seeded_rng(config.seed);
+        let num_boids = usize::from(config.num_boids);
+        (0..num_boids)
+            .map(|boid_idx| {
+                let position = config
+                    .spawn
+                    .position(boid_idx, num_boids, rect_max, &mut rng);
+                Self::new_random_boid(position, &mut rng)
+            })
+            .collect()
     }
-}
 
-unsafe impl Sync for BoidsDoubleBuffer {}
+    fn new_random_boid(position: Vec2, rng: &mut rand_chacha::ChaCha8Rng) -> Boid {
+        // Randomized in f32 either way, so the f32 and f64 builds start from the same flock
+        let new_boid = |position: Vec2, vel_angle: f32| {
+            Boid::new(
+                to_real(position),
+                to_real(Vec2::new(vel_angle.cos(), vel_angle.sin())) * MAX_SPEED / 2.0,
+            )
+        };
 
-pub struct MainState {
-    boids: BoidsDoubleBuffer,
-    is_attracted: bool,
-    rect_max: Vec2,
+        new_boid(position, rng.gen_range(0.0..std::f32::consts::TAU))
+    }
 }
 
-impl MainState {
-    pub fn new(num_boids: u16, rect_max: Vec2) -> GameResult<MainState> {
-        let mut rng = rand_chacha::ChaCha8Rng::from_seed([0; 32]);
-        let mut active_boids = vec![];
-        for _ in 0..num_boids {
-            active_boids.push(Self::new_random_boid(rect_max, &mut rng));
-        }
-        Ok(MainState {
-            boids: BoidsDoubleBuffer::new(active_boids),
-            is_attracted: false,
-            rect_max,
-        })
+impl Simulation for Flock {
+    fn name(&self) -> &'static str {
+        "threaded"
     }
 
-    fn new_random_boid(rect_max: Vec2, rng: &mut rand_chacha::ChaCha8Rng) -> Boid {
-        let new_boid = |position: Vec2, vel_angle: f32| {
-            let boid = Boid::new(
-                position,
-                Vec2::new(vel_angle.cos(), vel_angle.sin()) * MAX_SPEED / 2.0,
-            );
-            boid
-        };
+    fn num_boids(&self) -> usize {
+        self.boids.get_current_boids().len()
+    }
 
-        new_boid(
-            Vec2::new(
-                rng.gen_range(0.0..rect_max.x),
-                rng.gen_range(0.0..rect_max.y),
-            ),
-            rng.gen_range(0.0..std::f32::consts::TAU),
-        )
+    fn position(&self, boid_idx: usize) -> RealVec2 {
+        self.boids.get_current_boids()[boid_idx].position
     }
 
-    fn make_boid_mesh(&self, ctx: &mut Context) -> GameResult<graphics::Mesh> {
-        let p1 = Vec2::new(BOID_SIZE, 0f32);
-        let p2 = Vec2::new(0f32, BOID_SIZE / 2.0f32);
-        let p3 = Vec2::new(0f32, -BOID_SIZE / 2.0f32);
-        graphics::Mesh::new_polygon(
-            ctx,
-            graphics::DrawMode::fill(),
-            &[p1, p2, p3],
-            if self.is_attracted {
-                Color::BLUE
-            } else {
-                Color::RED
-            },
-        )
+    fn boids(&self) -> impl Iterator<Item = (RealVec2, RealVec2)> + '_ {
+        self.boids
+            .get_current_boids()
+            .iter()
+            .map(|boid| (boid.position, boid.velocity))
     }
-}
 
-impl EventHandler for MainState {
-    fn update(&mut self, ctx: &mut Context) -> GameResult {
-        tracy_scope!("update");
-        let dt = ctx.time.delta().as_secs_f32();
-        let mouse_pos = Vec2::new(ctx.mouse.position().x, ctx.mouse.position().y);
-        {
-            tracy_scope!("update_boids");
-            let boids_len = self.boids.get_current_boids().len();
-            #[cfg(not(feature = "no_false_sharing"))]
-            {
-                let core_count: usize = std::thread::available_parallelism()
-                    .unwrap_or(NonZero::new(1).unwrap())
-                    .into();
-                let num_chunks = (boids_len) / core_count;
-                (0..core_count)
-                    .into_par_iter()
-                    .with_min_len(1)
-                    .with_max_len(1)
-                    .for_each(|core_idx| {
-                        tracy_scope!("update_boids_thread");
-                        for chunk_idx in 0..num_chunks {
-                            let boid_idx = chunk_idx * core_count + core_idx;
-                            let current_boids = self.boids.get_current_boids();
-                            let next_boids = self.boids.get_next_boids();
-                            let boid = &current_boids[boid_idx];
-                            let acc = boid.calc_acceleration(
-                                boid_idx,
-                                &current_boids,
-                                mouse_pos,
-                                self.is_attracted,
-                            );
-                            let next_boid = &mut next_boids[boid_idx];
-                            std::hint::black_box(next_boid.position + next_boid.velocity);
-                            next_boid.update(dt, &boid, acc);
-                            next_boid.edges(self.rect_max.x, self.rect_max.y);
-                        }
-                    });
-            }
-            #[cfg(feature = "no_false_sharing")]
-            {
-                (0..boids_len)
-                    .into_par_iter()
-                    .with_min_len(8)
-                    .for_each(|boid_idx| {
-                        tracy_scope!("update_boids_thread");
-                        let current_boids = self.boids.get_current_boids();
-                        let next_boids = self.boids.get_next_boids();
-                        let boid = &current_boids[boid_idx];
-                        let acc = boid.calc_acceleration(
-                            boid_idx,
-                            &current_boids,
-                            mouse_pos,
-                            self.is_attracted,
-                        );
-                        next_boids[boid_idx].update(dt, &boid, acc);
-                        next_boids[boid_idx].edges(self.rect_max.x, self.rect_max.y);
-                    });
+    fn step(
+        &mut self,
+        steps: u32,
+        dt: Real,
+        rect_max: RealVec2,
+        attractors: &[RealVec2],
+        attraction_strength: Real,
+    ) -> u32 {
+        #[cfg(feature = "pool_stats")]
+        let parallel_start = std::time::Instant::now();
+        alloc_free!(pool "update_boids", {
+            for _ in 0..steps {
+                self.boids.step(
+                    self.par_chunks.then_some(self.par_chunk_len),
+                    attractors,
+                    attraction_strength,
+                    dt,
+                    rect_max,
+                    self.extra_work,
+                );
             }
-            self.boids.swap();
+        });
+        #[cfg(feature = "pool_stats")]
+        {
+            self.pool_stats = pool_stats::take_frame(parallel_start.elapsed());
+        }
+        #[cfg(feature = "flock_stats")]
+        {
+            tracy_scope!("flock_stats");
+            self.flock_stats = flock_stats::FlockStats::measure(self.boids.get_current_boids());
         }
-        Ok(())
+        steps
     }
 
-    fn draw(&mut self, ctx: &mut Context) -> GameResult {
-        tracy_scope!("draw");
-        let mut canvas = graphics::Canvas::from_frame(ctx, Color::WHITE);
+    #[cfg(feature = "bandwidth")]
+    fn step_bytes(&self) -> Option<(usize, usize)> {
+        Some((NEIGHBOR_BYTES, WRITTEN_BYTES))
+    }
 
-        {
-            tracy_scope!("draw_boids");
-            let boid_mesh = self.make_boid_mesh(ctx)?;
-            let current_boids = self.boids.get_current_boids();
-            for boid_cell in current_boids {
-                tracy_scope!("draw_boids");
-                boid_cell.draw(&mut canvas, &boid_mesh)?;
-            }
+    // Headings are worked out in parallel, in place, next to the positions
+    fn capture(&self, snapshot: &mut SimSnapshot, color: Color) {
+        let current_boids = self.boids.get_current_boids();
+        alloc_free!("snapshot", snapshot.resize(current_boids.len()));
+        alloc_free!(pool "snapshot", {
+            snapshot
+                .positions
+                .par_iter_mut()
+                .zip(snapshot.headings.par_iter_mut())
+                .zip(current_boids.par_iter())
+                .for_each(|((position, heading), boid)| {
+                    *position = to_render(boid.position);
+                    *heading = rotation_angle(to_render(boid.velocity));
+                });
+        });
+        snapshot.color = color;
+    }
+
+    // No AddBoids/RemoveBoids, the double buffer is sized once at startup
+    fn supports(&self, action: Action) -> bool {
+        action == Action::ToggleParChunks
+    }
+
+    fn apply_action(&mut self, action: Action) {
+        if action == Action::ToggleParChunks {
+            self.par_chunks = !self.par_chunks;
         }
+    }
 
-        {
-            tracy_scope!("draw_ui");
-            let fps_text = Text::new(format!("FPS: {:.2}", ctx.time.fps()));
-            canvas.draw(
-                &fps_text,
-                DrawParam::new()
-                    .dest(Vec2::new(10.0, 10.0))
-                    .color(Color::BLACK),
-            );
+    fn action_state(&self, action: Action) -> Option<String> {
+        match action {
+            Action::ToggleParChunks => Some(if self.par_chunks {
+                format!("par_chunks_mut({})", self.par_chunk_len)
+            } else {
+                "into_par_iter".to_string()
+            }),
+            _ => None,
+        }
+    }
 
-            let frametime_text = Text::new(format!(
-                "Frame time: {:.2} us",
-                ctx.time.delta().as_micros()
+    #[cfg_attr(
+        not(any(feature = "flock_stats", feature = "pool_stats")),
+        allow(unused_variables)
+    )]
+    fn draw_overlay(&self, canvas: &mut graphics::Canvas, palette: &Palette) {
+        #[cfg(feature = "flock_stats")]
+        canvas.draw(
+            &Text::new(flock_stats::FlockStats::text(self.flock_stats)),
+            DrawParam::new()
+                .dest(Vec2::new(10.0, 150.0))
+                .color(palette.text),
+        );
+
+        #[cfg(feature = "pool_stats")]
+        {
+            let pool_stats_text = Text::new(format!(
+                "Pool: {} workers, tasks {}..{}, idle {:.0}%",
+                self.pool_stats.workers,
+                self.pool_stats.min_tasks,
+                self.pool_stats.max_tasks,
+                self.pool_stats.idle * 100.0
             ));
             canvas.draw(
-                &frametime_text,
+                &pool_stats_text,
                 DrawParam::new()
-                    .dest(Vec2::new(10.0, 20.0))
-                    .color(Color::BLACK),
+                    .dest(Vec2::new(10.0, 100.0))
+                    .color(palette.text),
             );
+        }
+    }
+}
 
-            let boid_count_text =
-                Text::new(format!("Boids: {}", self.boids.get_current_boids().len()));
-            canvas.draw(
-                &boid_count_text,
-                DrawParam::new()
-                    .dest(Vec2::new(10.0, 50.0))
-                    .color(Color::BLACK),
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use boids_common::test_support;
+
+    fn ring(count: usize, radius: Real, speed: Real) -> Vec<Boid> {
+        test_support::ring(count, radius, speed)
+            .map(|(position, velocity)| Boid::new(position, velocity))
+            .collect()
+    }
+
+    #[test]
+    fn step_writes_every_boid() {
+        let core_count = std::thread::available_parallelism().map_or(1, NonZero::get);
+        let rect_max = RealVec2::new(1080.0, 800.0);
+        let center = rect_max / 2.0;
+        let boids: Vec<Boid> = ring(core_count * 3 + 1, 40.0, 10.0)
+            .into_iter()
+            .map(|boid| Boid::new(boid.position + center, boid.velocity))
+            .collect();
+        let mut buffer = BoidsDoubleBuffer::new(boids.clone());
+        buffer.step(None, &[], 0.0, 0.01, rect_max, 0);
+        for (boid_idx, (boid, stepped)) in boids.iter().zip(buffer.get_current_boids()).enumerate()
+        {
+            let mut expected = Boid::default();
+            expected.update(
+                0.01,
+                boid,
+                boid.calc_acceleration(boid_idx, &boids, &[], 0.0),
             );
+            expected.edges(rect_max.x, rect_max.y);
+            assert_eq!(stepped.position, expected.position, "boid {boid_idx}");
+            assert_eq!(stepped.velocity, expected.velocity, "boid {boid_idx}");
         }
+    }
 
-        canvas.finish(ctx)?;
+    #[test]
+    fn tuned_thread_count_is_one_of_the_candidates() {
+        let available = std::thread::available_parallelism().map_or(1, NonZero::get);
+        let num_threads = tune_thread_count(&Config::default(), Vec2::new(1080.0, 800.0));
+        assert!(num_threads == available || num_threads.is_power_of_two());
+        assert!((1..=available).contains(&num_threads));
+    }
 
-        tracy_client::frame_mark();
-        Ok(())
+    #[test]
+    fn tree_sums_do_not_depend_on_the_thread_count() {
+        let rect_max = Vec2::new(1080.0, 800.0);
+        let mut rng = seeded_rng(7);
+        let boids: Vec<Boid> = (0..5000)
+            .map(|boid_idx| {
+                let position = Spawn::Uniform.position(boid_idx, 5000, rect_max, &mut rng);
+                Flock::new_random_boid(position, &mut rng)
+            })
+            .collect();
+        let sums_on = |num_threads| {
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(num_threads)
+                .build()
+                .unwrap();
+            pool.install(|| {
+                (
+                    flock_stats::tree_sums(&boids),
+                    flock_stats::atomic_sums(&boids),
+                )
+            })
+        };
+        let (serial, _) = sums_on(1);
+        // The position sums run into the millions, where the atomic adds lose a few units to
+        // their order
+        let position_tolerance = serial.position.length() * 1e-5;
+        for num_threads in [2, 3, 8] {
+            let (tree, atomic) = sums_on(num_threads);
+            assert_eq!(tree, serial, "on {num_threads} threads");
+            assert!(
+                atomic
+                    .position
+                    .abs_diff_eq(serial.position, position_tolerance)
+                    && atomic.heading.abs_diff_eq(serial.heading, 1e-2),
+                "on {num_threads} threads"
+            );
+        }
     }
 }
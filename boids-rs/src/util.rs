@@ -1,13 +1,476 @@
-pub const BOID_SIZE: f32 = 10.0;
-pub const MAX_SPEED: f32 = 100.0;
-pub const MAX_FORCE: f32 = 80.0;
-pub const PERCEPTION: f32 = 100.0;
-pub const SEPARATION: f32 = 100.0;
-
-macro_rules! tracy_scope {
-    ($name:literal) => {
-        let _tracy_span = tracy_client::span!($name);
+use ggez::event::winit_event::TouchPhase;
+use ggez::event::{Axis, Button};
+use ggez::graphics::{self, Color};
+use ggez::{Context, GameResult};
+use glam::Vec2;
+
+pub use boids_common::*;
+
+const GAMEPAD_DEADZONE: f32 = 0.15;
+const GAMEPAD_CURSOR_SPEED: f32 = 600.0;
+const GAMEPAD_CURSOR_RADIUS: f32 = 6.0;
+
+pub(crate) use perf_instrument::{tracy_message, tracy_scope};
+
+/// Microphone input for the `audio` feature. The capture callback runs on cpal's own thread and
+/// only publishes the RMS level of each buffer; the simulation turns that into a pulse on beat
+/// onsets, which drives an attraction towards the screen center.
+#[cfg(feature = "audio")]
+pub mod audio {
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+
+    use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+
+    // An onset is a buffer this much louder than the running average, and above the noise floor
+    const ONSET_RATIO: f32 = 1.5;
+    const NOISE_FLOOR: f32 = 0.02;
+    // Per second: how fast the running average follows the level, and how fast a pulse fades
+    const AVERAGE_RATE: f32 = 2.0;
+    const PULSE_DECAY: f32 = 3.0;
+
+    pub struct AudioPulse {
+        _stream: cpal::Stream,
+        level: Arc<AtomicU32>,
+        average: f32,
+        pulse: f32,
+    }
+
+    impl AudioPulse {
+        /// `None` if there is no usable input device, the demo then just runs without the pulse.
+        pub fn open() -> Option<AudioPulse> {
+            let device = cpal::default_host().default_input_device()?;
+            let config = device.default_input_config().ok()?;
+            let level = Arc::new(AtomicU32::new(0));
+            let callback_level = level.clone();
+            let on_error = |err| eprintln!("Audio input error: {err}");
+            let stream = match config.sample_format() {
+                cpal::SampleFormat::F32 => device.build_input_stream(
+                    &config.into(),
+                    move |data: &[f32], _: &_| store_rms(&callback_level, data.iter().copied()),
+                    on_error,
+                    None,
+                ),
+                cpal::SampleFormat::I16 => device.build_input_stream(
+                    &config.into(),
+                    move |data: &[i16], _: &_| {
+                        let samples = data.iter().map(|&s| s as f32 / i16::MAX as f32);
+                        store_rms(&callback_level, samples)
+                    },
+                    on_error,
+                    None,
+                ),
+                _ => return None,
+            }
+            .ok()?;
+            stream.play().ok()?;
+            Some(AudioPulse {
+                _stream: stream,
+                level,
+                average: 0.0,
+                pulse: 0.0,
+            })
+        }
+
+        /// The pulse strength for this frame: 1.0 on an onset, fading back to 0.0 after it.
+        pub fn update(&mut self, dt: f32) -> f32 {
+            let level = f32::from_bits(self.level.load(Ordering::Relaxed));
+            if level > NOISE_FLOOR && level > self.average * ONSET_RATIO {
+                self.pulse = 1.0;
+            } else {
+                self.pulse = (self.pulse - PULSE_DECAY * dt).max(0.0);
+            }
+            self.average += (level - self.average) * (AVERAGE_RATE * dt).min(1.0);
+            self.pulse
+        }
+    }
+
+    fn store_rms(level: &AtomicU32, samples: impl Iterator<Item = f32>) {
+        let (sum, count) = samples.fold((0.0, 0), |(sum, count), s| (sum + s * s, count + 1));
+        if count > 0 {
+            let rms: f32 = (sum / count as f32).sqrt();
+            level.store(rms.to_bits(), Ordering::Relaxed);
+        }
+    }
+}
+
+/// Per-frame rayon pool numbers for the `pool_stats` feature: how many tasks each worker ran and
+/// how much of the parallel section the workers spent idle. Rayon doesn't expose its steal
+/// counters, but the spread between the busiest and the least busy worker shows the same thing:
+/// with perfect balance every worker runs the same number of tasks. Each worker only writes its
+/// own cache-line-sized slot, so recording doesn't add contention of its own.
+#[cfg(feature = "pool_stats")]
+pub mod pool_stats {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::OnceLock;
+    use std::time::{Duration, Instant};
+
+    #[derive(Default)]
+    #[repr(align(64))]
+    struct WorkerCounters {
+        tasks: AtomicU64,
+        busy_ns: AtomicU64,
+    }
+
+    static WORKERS: OnceLock<Vec<WorkerCounters>> = OnceLock::new();
+
+    // Sized for the largest pool that can show up, `--auto-threads` runs tasks on smaller
+    // pools before the global one exists. Stats only look at the global pool's workers.
+    fn workers() -> &'static [WorkerCounters] {
+        WORKERS.get_or_init(|| {
+            let available = std::thread::available_parallelism().map_or(1, |count| count.get());
+            (0..available.max(rayon::current_num_threads()))
+                .map(|_| WorkerCounters::default())
+                .collect()
+        })
+    }
+
+    /// Charges the time until it is dropped to the current worker, see `pool_task!`.
+    pub struct Task {
+        start: Instant,
+    }
+
+    impl Task {
+        pub fn start() -> Task {
+            Task {
+                start: Instant::now(),
+            }
+        }
+    }
+
+    impl Drop for Task {
+        fn drop(&mut self) {
+            let worker = rayon::current_thread_index().and_then(|idx| workers().get(idx));
+            if let Some(worker) = worker {
+                let busy_ns = self.start.elapsed().as_nanos() as u64;
+                worker.tasks.fetch_add(1, Ordering::Relaxed);
+                worker.busy_ns.fetch_add(busy_ns, Ordering::Relaxed);
+            }
+        }
+    }
+
+    #[derive(Debug, Default, Clone, Copy)]
+    pub struct Stats {
+        pub workers: usize,
+        pub min_tasks: u64,
+        pub max_tasks: u64,
+        pub idle: f32,
+    }
+
+    /// Returns the stats since the last call and resets them. `wall` is how long the parallel
+    /// section took, the idle share is measured against `wall` times the number of workers.
+    pub fn take_frame(wall: Duration) -> Stats {
+        let workers = workers();
+        let workers = &workers[..rayon::current_num_threads().min(workers.len())];
+        let mut stats = Stats {
+            workers: workers.len(),
+            min_tasks: u64::MAX,
+            ..Stats::default()
+        };
+        let mut busy_ns = 0;
+        for worker in workers {
+            let tasks = worker.tasks.swap(0, Ordering::Relaxed);
+            stats.min_tasks = stats.min_tasks.min(tasks);
+            stats.max_tasks = stats.max_tasks.max(tasks);
+            busy_ns += worker.busy_ns.swap(0, Ordering::Relaxed);
+        }
+        let available_ns = wall.as_nanos() as f64 * workers.len() as f64;
+        if available_ns > 0.0 {
+            stats.idle = (1.0 - busy_ns as f64 / available_ns).max(0.0) as f32;
+        }
+        stats
+    }
+}
+
+macro_rules! pool_task {
+    () => {
+        #[cfg(feature = "pool_stats")]
+        let _pool_task = crate::util::pool_stats::Task::start();
     };
 }
 
-pub(crate) use tracy_scope;
+pub(crate) use pool_task;
+
+// Mouse and touch positions arrive in physical pixels, while the window is sized and drawn in logical
+// ones.
+pub fn to_logical(ctx: &Context, physical: Vec2) -> Vec2 {
+    physical / ctx.gfx.window().scale_factor() as f32
+}
+
+/// Lets the demo be driven from the podium: the left stick moves an attractor cursor and the
+/// buttons map to actions through `BINDINGS`.
+#[derive(Default)]
+pub struct GamepadInput {
+    stick: Vec2,
+    /// Set while the gamepad is the last used pointing device, cleared by mouse movement.
+    pub cursor: Option<Vec2>,
+}
+
+impl GamepadInput {
+    pub fn axis_changed(&mut self, axis: Axis, value: f32) {
+        let value = if value.abs() < GAMEPAD_DEADZONE {
+            0.0
+        } else {
+            value
+        };
+        match axis {
+            Axis::LeftStickX => self.stick.x = value,
+            // Stick up is positive, screen up is negative
+            Axis::LeftStickY => self.stick.y = -value,
+            _ => {}
+        }
+    }
+
+    pub fn button_action(&self, button: Button) -> Option<Action> {
+        BINDINGS
+            .iter()
+            .find(|binding| binding.button == Some(button))
+            .map(|binding| binding.action)
+    }
+
+    pub fn update(&mut self, dt: f32, rect_max: Vec2) {
+        if self.stick == Vec2::ZERO {
+            return;
+        }
+        let cursor = self.cursor.unwrap_or(rect_max / 2.0);
+        self.cursor =
+            Some((cursor + self.stick * GAMEPAD_CURSOR_SPEED * dt).clamp(Vec2::ZERO, rect_max));
+    }
+}
+
+pub fn draw_gamepad_cursor(
+    ctx: &mut Context,
+    canvas: &mut graphics::Canvas,
+    cursor: Vec2,
+    color: Color,
+) -> GameResult {
+    let cursor_mesh = graphics::Mesh::new_circle(
+        ctx,
+        graphics::DrawMode::stroke(2.0),
+        cursor,
+        GAMEPAD_CURSOR_RADIUS,
+        0.5,
+        color,
+    )?;
+    canvas.draw(&cursor_mesh, graphics::DrawParam::new());
+    Ok(())
+}
+
+/// Active touch points, each one acting as an attractor. ggez doesn't pass the finger id to
+/// `touch_event`, so moves and releases are matched to the closest known touch.
+#[derive(Default)]
+pub struct TouchInput {
+    pub points: Vec<Vec2>,
+}
+
+impl TouchInput {
+    pub fn handle(&mut self, phase: TouchPhase, position: Vec2) {
+        match phase {
+            TouchPhase::Started => self.points.push(position),
+            TouchPhase::Moved => {
+                if let Some(idx) = self.closest(position) {
+                    self.points[idx] = position;
+                }
+            }
+            TouchPhase::Ended | TouchPhase::Cancelled => {
+                if let Some(idx) = self.closest(position) {
+                    self.points.swap_remove(idx);
+                }
+            }
+        }
+    }
+
+    fn closest(&self, position: Vec2) -> Option<usize> {
+        self.points
+            .iter()
+            .enumerate()
+            .min_by(|(_, a), (_, b)| {
+                a.distance_squared(position)
+                    .total_cmp(&b.distance_squared(position))
+            })
+            .map(|(idx, _)| idx)
+    }
+}
+
+/// Boids boxed in by dragging with the left mouse button, kept by index so the highlight stays on
+/// the same sub-flock. While tracking, the view is centered on their centroid.
+#[derive(Default, Clone)]
+pub struct Selection {
+    /// Drag start and current corner, in window coordinates.
+    drag: Option<(Vec2, Vec2)>,
+    pub indices: Vec<usize>,
+    pub tracking: bool,
+    /// Top-left corner of the view in simulation coordinates.
+    pub camera: Vec2,
+}
+
+impl Selection {
+    pub fn begin_drag(&mut self, position: Vec2) {
+        self.drag = Some((position, position));
+    }
+
+    pub fn drag_to(&mut self, position: Vec2) {
+        if let Some((_, end)) = &mut self.drag {
+            *end = position;
+        }
+    }
+
+    pub fn drag_rect(&self) -> Option<graphics::Rect> {
+        self.drag.map(|(start, end)| {
+            let min = start.min(end);
+            let size = (start - end).abs();
+            graphics::Rect::new(min.x, min.y, size.x, size.y)
+        })
+    }
+
+    /// Replaces the selection with the boids inside the dragged box. A click that barely moved
+    /// clears it.
+    pub fn finish_drag(&mut self, positions: impl Iterator<Item = Vec2>) {
+        let Some(rect) = self.drag_rect() else {
+            return;
+        };
+        self.drag = None;
+        self.indices.clear();
+        if rect.w < 2.0 && rect.h < 2.0 {
+            return;
+        }
+        let camera = self.camera;
+        self.indices.extend(
+            positions
+                .enumerate()
+                .filter(|&(_, position)| rect.contains(position - camera))
+                .map(|(idx, _)| idx),
+        );
+    }
+
+    pub fn draw_drag(
+        &self,
+        ctx: &mut Context,
+        canvas: &mut graphics::Canvas,
+        color: Color,
+    ) -> GameResult {
+        if let Some(rect) = self.drag_rect() {
+            let box_mesh =
+                graphics::Mesh::new_rectangle(ctx, graphics::DrawMode::stroke(1.0), rect, color)?;
+            canvas.draw(&box_mesh, graphics::DrawParam::new());
+        }
+        Ok(())
+    }
+
+    /// Removed boids come off the end of the flock.
+    pub fn truncate(&mut self, num_boids: usize) {
+        self.indices.retain(|&idx| idx < num_boids);
+    }
+
+    pub fn toggle_tracking(&mut self) {
+        self.tracking = !self.tracking;
+        if !self.tracking {
+            self.camera = Vec2::ZERO;
+        }
+    }
+
+    /// Moves the camera onto the centroid of the selection. Offsets are taken from the first
+    /// selected boid and wrapped, so a group straddling an edge doesn't average to the middle.
+    pub fn follow(&mut self, position: impl Fn(usize) -> Vec2, rect_max: Vec2) {
+        let Some(&first) = self.indices.first() else {
+            return;
+        };
+        if !self.tracking {
+            return;
+        }
+        let anchor = position(first);
+        let offsets: Vec2 = self
+            .indices
+            .iter()
+            .map(|&idx| {
+                let offset = position(idx) - anchor;
+                offset - (offset / rect_max).round() * rect_max
+            })
+            .sum();
+        let centroid = anchor + offsets / self.indices.len() as f32;
+        self.camera = centroid.rem_euclid(rect_max) - rect_max / 2.0;
+    }
+}
+
+const ATTRACT_CYCLE: f32 = 20.0;
+const ATTRACT_ACTIVE: f32 = 12.0;
+const ATTRACT_PALETTE_PERIOD: f32 = 60.0;
+/// How far the camera drifts from the origin, as a share of the screen.
+const ATTRACT_PAN: f32 = 0.05;
+
+/// `--attract-mode`, for leaving the demo running at a booth with nobody at the controls. An
+/// attractor wanders the screen on a slow Lissajous path for part of every cycle, pulling harder
+/// towards the middle of it, so the flock alternates between chasing and relaxing. The camera
+/// drifts a little and the palette rotates every minute.
+#[derive(Default)]
+pub struct AttractMode {
+    time: f32,
+}
+
+impl AttractMode {
+    pub fn update(&mut self, dt: f32) {
+        self.time += dt;
+    }
+
+    pub fn attractor(&self, rect_max: Vec2) -> Option<Vec2> {
+        if self.time % ATTRACT_CYCLE > ATTRACT_ACTIVE {
+            return None;
+        }
+        let wander = Vec2::new((self.time * 0.13).sin(), (self.time * 0.21).sin());
+        Some(rect_max / 2.0 + wander * rect_max * 0.4)
+    }
+
+    /// Eases in and out over the active part of the cycle, so the flock isn't yanked around.
+    pub fn attraction_strength(&self) -> f32 {
+        let phase = (self.time % ATTRACT_CYCLE) / ATTRACT_ACTIVE;
+        (phase * std::f32::consts::PI).sin().max(0.0)
+    }
+
+    pub fn camera(&self, rect_max: Vec2) -> Vec2 {
+        let drift = Vec2::new((self.time * 0.05).sin(), (self.time * 0.08).sin());
+        drift * rect_max * ATTRACT_PAN
+    }
+
+    pub fn palette(&self) -> Palette {
+        let idx = (self.time / ATTRACT_PALETTE_PERIOD) as usize;
+        Palette::ALL[idx % Palette::ALL.len()]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tracking_centers_a_selection_split_across_the_edge() {
+        let rect_max = Vec2::new(1000.0, 800.0);
+        let positions = [
+            Vec2::new(990.0, 400.0),
+            Vec2::new(20.0, 400.0),
+            Vec2::new(500.0, 100.0),
+        ];
+        let mut selection = Selection::default();
+        selection.begin_drag(Vec2::new(0.0, 300.0));
+        selection.drag_to(Vec2::new(1000.0, 500.0));
+        selection.finish_drag(positions.iter().copied());
+        assert_eq!(selection.indices, [0, 1]);
+
+        selection.toggle_tracking();
+        selection.follow(|idx| positions[idx], rect_max);
+        let centroid = selection.camera + rect_max / 2.0;
+        assert!(
+            centroid.abs_diff_eq(Vec2::new(5.0, 400.0), 1e-3),
+            "{centroid}"
+        );
+    }
+
+    #[test]
+    fn attract_mode_eases_the_pull_in_and_out() {
+        let mut attract_mode = AttractMode::default();
+        assert_eq!(attract_mode.attraction_strength(), 0.0);
+        attract_mode.update(ATTRACT_ACTIVE / 2.0);
+        assert!((attract_mode.attraction_strength() - 1.0).abs() < 1e-6);
+        attract_mode.update(ATTRACT_ACTIVE / 2.0 - 0.01);
+        assert!(attract_mode.attraction_strength() < 0.01);
+    }
+}
@@ -0,0 +1,24 @@
+//! Builds the SIMD flock on `std::simd` when the compiler is a nightly one. Stable toolchains get
+//! the scalar flock of boids-common instead.
+
+use std::env;
+use std::process::Command;
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-env-changed=RUSTC");
+    println!("cargo:rustc-check-cfg=cfg(portable_simd)");
+
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let version = Command::new(rustc)
+        .arg("-vV")
+        .output()
+        .map(|output| String::from_utf8_lossy(&output.stdout).into_owned())
+        .unwrap_or_default();
+    let is_nightly = version.lines().any(|line| {
+        line.starts_with("release:") && (line.contains("nightly") || line.contains("dev"))
+    });
+    if is_nightly {
+        println!("cargo:rustc-cfg=portable_simd");
+    }
+}
@@ -1,604 +1,302 @@
-use core::num;
-use std::cell::UnsafeCell;
-use std::simd::cmp::{SimdPartialEq, SimdPartialOrd};
+use std::time::Duration;
 
+use boids_common::scalar::Boid;
+use boids_common::*;
 use ggez::event::EventHandler;
-use ggez::graphics::{self, Color, DrawParam, Text};
+use ggez::graphics::{self, Color, DrawParam};
 use ggez::{Context, GameResult};
 use glam::Vec2;
-use rand::{Rng, SeedableRng};
-use rayon::prelude::*;
+use perf_instrument::tracy_scope;
+use rand::Rng;
 
-use seq_macro::seq;
+use crate::config::Config;
 
-use std::simd::{f32x8, Mask, StdFloat};
-
-pub const BOID_SIZE: f32 = 10.0;
-pub const MAX_SPEED: f32 = 100.0;
-pub const MAX_FORCE: f32 = 80.0;
-pub const PERCEPTION: f32 = 100.0;
-pub const SEPARATION: f32 = 100.0;
-
-const EPSILON: f32 = 0.0001;
-
-macro_rules! tracy_scope {
-    ($name:literal) => {
-        let _tracy_span = tracy_client::span!($name);
-    };
-}
-
-const CHUNK_SIZE: usize = 8;
-
-#[derive(Debug, Clone, Copy)]
-struct SimdVec2 {
-    x: f32x8,
-    y: f32x8,
-}
-
-impl SimdVec2 {
-    fn new_splat_all(v: f32) -> Self {
-        SimdVec2 {
-            x: f32x8::splat(v),
-            y: f32x8::splat(v),
-        }
-    }
-
-    fn zero() -> Self {
-        SimdVec2 {
-            x: f32x8::splat(0.0),
-            y: f32x8::splat(0.0),
-        }
-    }
-
-    fn new(x: f32x8, y: f32x8) -> Self {
-        SimdVec2 { x, y }
-    }
-
-    fn normalize(&self) -> Self {
-        let length = self.length();
-        let x = self.x / length;
-        let y = self.y / length;
-        SimdVec2 { x, y }
-    }
-
-    fn select(&self, mask: MaskType, other: Self) -> Self {
-        let x = mask.select(self.x, other.x);
-        let y = mask.select(self.y, other.y);
-        SimdVec2 { x, y }
-    }
-
-    fn length(&self) -> f32x8 {
-        (self.x * self.x + self.y * self.y).sqrt()
-    }
-
-    fn length_squared(&self) -> f32x8 {
-        self.x * self.x + self.y * self.y
-    }
-
-    fn clamp_length_max(&self, max: f32) -> Self {
-        let length_sqr = self.length_squared();
-        let max_simd = f32x8::splat(max);
-        let mask = length_sqr.simd_gt(max_simd * max_simd);
-        let x = mask.select(max_simd * (self.x / length_sqr.sqrt()), self.x);
-        let y = mask.select(max_simd * (self.y / length_sqr.sqrt()), self.y);
-        SimdVec2 { x, y }
-    }
-
-    fn rotate_elements_right<const OFFSET: usize>(&self) -> Self {
-        let x = self.x.rotate_elements_right::<OFFSET>();
-        let y = self.y.rotate_elements_right::<OFFSET>();
-        SimdVec2 { x, y }
-    }
-}
-
-impl std::ops::Add for SimdVec2 {
-    type Output = Self;
-    fn add(self, rhs: Self) -> Self {
-        SimdVec2::new(self.x + rhs.x, self.y + rhs.y)
-    }
-}
-
-impl std::ops::Sub for SimdVec2 {
-    type Output = Self;
-    fn sub(self, rhs: Self) -> Self {
-        SimdVec2::new(self.x - rhs.x, self.y - rhs.y)
-    }
-}
-
-impl std::ops::Mul<f32x8> for SimdVec2 {
-    type Output = Self;
-    fn mul(self, rhs: f32x8) -> Self {
-        SimdVec2::new(self.x * rhs, self.y * rhs)
-    }
-}
-
-impl std::ops::Div<f32x8> for SimdVec2 {
-    type Output = Self;
-    fn div(self, rhs: f32x8) -> Self {
-        SimdVec2::new(self.x / rhs, self.y / rhs)
-    }
-}
-
-impl std::ops::AddAssign for SimdVec2 {
-    fn add_assign(&mut self, rhs: Self) {
-        self.x += rhs.x;
-        self.y += rhs.y;
-    }
-}
-
-type MaskType = Mask<i32, CHUNK_SIZE>;
-fn simd_is_close_enough(lhs: &SimdVec2, rhs: &SimdVec2, max_dist: f32) -> MaskType {
-    let distance_squared = (*lhs - *rhs).length_squared();
-    let simd_dist_sqr = f32x8::splat(max_dist * max_dist);
-    distance_squared.simd_le(simd_dist_sqr)
-}
-
-fn simd_epsilon_check(lhs: &SimdVec2, rhs: &SimdVec2) -> MaskType {
-    let distance_squared = (*lhs - *rhs).length_squared();
-    let simd_epsilon = f32x8::splat(EPSILON * EPSILON);
-    distance_squared.simd_gt(simd_epsilon)
-}
-
-struct BoidsVec {
-    pos_x: Vec<f32>,
-    pos_y: Vec<f32>,
-    vel_x: Vec<f32>,
-    vel_y: Vec<f32>,
-}
-
-impl BoidsVec {
-    fn new_from_scalar(scalar_vec: &[Boid]) -> Self {
-        let mut pos_x = Vec::with_capacity(scalar_vec.len());
-        let mut pos_y = Vec::with_capacity(scalar_vec.len());
-        let mut vel_x = Vec::with_capacity(scalar_vec.len());
-        let mut vel_y = Vec::with_capacity(scalar_vec.len());
-
-        for boid in scalar_vec {
-            pos_x.push(boid.position.x);
-            pos_y.push(boid.position.y);
-            vel_x.push(boid.velocity.x);
-            vel_y.push(boid.velocity.y);
-        }
-
-        BoidsVec {
-            pos_x,
-            pos_y,
-            vel_x,
-            vel_y,
-        }
-    }
-
-    fn new_with_length(len: usize) -> Self {
-        BoidsVec {
-            pos_x: vec![0.0; len],
-            pos_y: vec![0.0; len],
-            vel_x: vec![0.0; len],
-            vel_y: vec![0.0; len],
-        }
-    }
-
-    #[inline(always)]
-    fn whole_boids_at(&self, chunk_idx: usize) -> (SimdVec2, SimdVec2) {
-        let start = chunk_idx * CHUNK_SIZE;
-        let end = start + CHUNK_SIZE;
-        let pos = SimdVec2::new(
-            f32x8::from_slice(&self.pos_x[start..end]),
-            f32x8::from_slice(&self.pos_y[start..end]),
-        );
-        let vel = SimdVec2::new(
-            f32x8::from_slice(&self.vel_x[start..end]),
-            f32x8::from_slice(&self.vel_y[start..end]),
-        );
-        (pos, vel)
-    }
-
-    #[inline(always)]
-    fn boids_pos_at(&self, chunk_idx: usize) -> SimdVec2 {
-        let start = chunk_idx * CHUNK_SIZE;
-        let end = start + CHUNK_SIZE;
-        SimdVec2::new(
-            f32x8::from_slice(&self.pos_x[start..end]),
-            f32x8::from_slice(&self.pos_y[start..end]),
-        )
-    }
-
-    #[inline(always)]
-    fn alignment_for_permutation<const PERM: usize>(
-        alignment: &mut SimdVec2,
-        total: &mut f32x8,
-        this_pos: &SimdVec2,
-        other_pos: &SimdVec2,
-        other_vel: &SimdVec2,
-    ) {
-        let other_pos = other_pos.rotate_elements_right::<PERM>();
-        let other_vel = other_vel.rotate_elements_right::<PERM>();
-        let is_close_mask = simd_is_close_enough(&this_pos, &other_pos, PERCEPTION);
-        let epsilon_mask = simd_epsilon_check(&this_pos, &other_pos);
-        let mask = is_close_mask & epsilon_mask;
-        let one_or_zero = mask.select(f32x8::splat(1.0), f32x8::splat(0.0));
-        *alignment += other_vel * one_or_zero;
-        *total += one_or_zero;
-    }
-
-    #[inline(never)]
-    fn alignment(&self, chunk_idx: usize) -> SimdVec2 {
-        let mut alignment: SimdVec2 = SimdVec2::new_splat_all(0.0);
-        let mut total: f32x8 = f32x8::splat(0.0);
-
-        let (this_pos, this_vel) = self.whole_boids_at(chunk_idx);
-        for other_chunk_idx in 0..self.num_chunks() {
-            let (other_pos, other_vel) = self.whole_boids_at(other_chunk_idx);
-            seq!(N in 0..8 {
-                Self::alignment_for_permutation::<N>(
-                    &mut alignment,
-                    &mut total,
-                    &this_pos,
-                    &other_pos,
-                    &other_vel,
-                );
-            });
-        }
-
-        let total_mask = total.simd_ne(f32x8::splat(0.0));
-        alignment = alignment / total;
-        alignment = alignment.normalize() * f32x8::splat(MAX_SPEED);
-        alignment = alignment - this_vel;
-        alignment = alignment.clamp_length_max(MAX_FORCE);
-        alignment.select(total_mask, SimdVec2::zero())
-    }
-
-    #[inline(always)]
-    fn cohesion_for_permutation<const PERM: usize>(
-        cohesion: &mut SimdVec2,
-        total: &mut f32x8,
-        this_pos: &SimdVec2,
-        other_pos: &SimdVec2,
-    ) {
-        let other_pos = other_pos.rotate_elements_right::<PERM>();
-        let is_close_mask = simd_is_close_enough(&this_pos, &other_pos, PERCEPTION);
-        let epsilon_mask = simd_epsilon_check(&this_pos, &other_pos);
-        let mask = is_close_mask & epsilon_mask;
-        let one_or_zero = mask.select(f32x8::splat(1.0), f32x8::splat(0.0));
-        *cohesion += other_pos * one_or_zero;
-        *total += one_or_zero;
-    }
-
-    #[inline(never)]
-    fn cohesion(&self, chunk_idx: usize) -> SimdVec2 {
-        let mut cohesion: SimdVec2 = SimdVec2::new_splat_all(0.0);
-        let mut total: f32x8 = f32x8::splat(0.0);
-
-        let (this_pos, this_vel) = self.whole_boids_at(chunk_idx);
-        for other_chunk_idx in 0..self.num_chunks() {
-            let other_pos = self.boids_pos_at(other_chunk_idx);
-            seq!(N in 0..8 {
-                Self::cohesion_for_permutation::<N>(
-                    &mut cohesion,
-                    &mut total,
-                    &this_pos,
-                    &other_pos,
-                );
-            });
-        }
-
-        let total_mask = total.simd_ne(f32x8::splat(0.0));
-        cohesion = cohesion / total;
-        cohesion = (cohesion - this_pos).normalize() * f32x8::splat(MAX_SPEED);
-        cohesion = cohesion - this_vel;
-        cohesion = cohesion.clamp_length_max(MAX_FORCE);
-        cohesion.select(total_mask, SimdVec2::zero())
-    }
-
-    #[inline(always)]
-    fn separation_for_permutation<const PERM: usize>(
-        separation: &mut SimdVec2,
-        total: &mut f32x8,
-        this_pos: &SimdVec2,
-        other_pos: &SimdVec2,
-    ) {
-        let other_pos = other_pos.rotate_elements_right::<PERM>();
-        let diff = *this_pos - other_pos;
-        let distance = diff.length();
-        let is_close_mask = distance.simd_le(f32x8::splat(SEPARATION));
-        let epsilon_mask = distance.simd_gt(f32x8::splat(EPSILON));
-        let mask = is_close_mask & epsilon_mask;
-        let separation_acc =
-            (diff.normalize() / distance).select(mask, SimdVec2::new_splat_all(0.0));
-        *separation += separation_acc;
-        *total += mask.select(f32x8::splat(1.0), f32x8::splat(0.0));
-    }
-
-    #[inline(never)]
-    fn separation(&self, chunk_idx: usize) -> SimdVec2 {
-        let mut separation: SimdVec2 = SimdVec2::new_splat_all(0.0);
-        let mut total: f32x8 = f32x8::splat(0.0);
-
-        let (this_pos, this_vel) = self.whole_boids_at(chunk_idx);
-        for other_chunk_idx in 0..self.num_chunks() {
-            let other_pos = self.boids_pos_at(other_chunk_idx);
-            seq!(N in 0..8 {
-                Self::separation_for_permutation::<N>(
-                    &mut separation,
-                    &mut total,
-                    &this_pos,
-                    &other_pos,
-                );
-            });
-        }
-
-        let total_mask = total.simd_ne(f32x8::splat(0.0));
-        separation = separation / total;
-        separation = separation.normalize() * f32x8::splat(MAX_SPEED);
-        separation = separation - this_vel;
-        separation = separation.clamp_length_max(MAX_FORCE);
-        separation.select(total_mask, SimdVec2::zero())
-    }
-
-    #[inline(never)]
-    fn calc_acceleration(&self, chunk_idx: usize) -> SimdVec2 {
-        let alignment = self.alignment(chunk_idx);
-        let cohesion = self.cohesion(chunk_idx);
-        let separation = self.separation(chunk_idx);
-        alignment + cohesion + separation
-    }
-
-    fn update(&mut self, chunk_idx: usize, dt: f32, source: &Self, screen_rect: Vec2) {
-        let start = chunk_idx * CHUNK_SIZE;
-        let end = start + CHUNK_SIZE;
-        let mut this_pos = SimdVec2::new(
-            f32x8::from_slice(&source.pos_x[start..end]),
-            f32x8::from_slice(&source.pos_y[start..end]),
-        );
-        let mut this_vel = SimdVec2::new(
-            f32x8::from_slice(&source.vel_x[start..end]),
-            f32x8::from_slice(&source.vel_y[start..end]),
-        );
-        let acceleration: SimdVec2 = source.calc_acceleration(chunk_idx);
-
-        let simd_dt = f32x8::splat(dt);
-        let this_frame_acceleration = std::hint::black_box(acceleration * simd_dt);
-        #[cfg(feature = "static_update")]
-        let this_frame_acceleration = SimdVec2::new_splat_all(0.0);
-
-        this_vel += this_frame_acceleration;
-
-        let this_frame_velocity = std::hint::black_box(this_vel * simd_dt);
-        #[cfg(feature = "static_update")]
-        let this_frame_velocity = SimdVec2::new_splat_all(0.0);
-
-        this_pos += this_frame_velocity;
-
-        // Edges
-        let mask_x = this_pos.x.simd_gt(f32x8::splat(screen_rect.x));
-        let mask_y = this_pos.y.simd_gt(f32x8::splat(screen_rect.y));
-        this_pos.x = mask_x.select(f32x8::splat(0.0), this_pos.x);
-        this_pos.y = mask_y.select(f32x8::splat(0.0), this_pos.y);
-
-        let mask_x = this_pos.x.simd_lt(f32x8::splat(0.0));
-        let mask_y = this_pos.y.simd_lt(f32x8::splat(0.0));
-        this_pos.x = mask_x.select(f32x8::splat(screen_rect.x), this_pos.x);
-        this_pos.y = mask_y.select(f32x8::splat(screen_rect.y), this_pos.y);
-
-        this_pos.x.copy_to_slice(&mut self.pos_x[start..end]);
-        this_pos.y.copy_to_slice(&mut self.pos_y[start..end]);
-        this_vel.x.copy_to_slice(&mut self.vel_x[start..end]);
-        this_vel.y.copy_to_slice(&mut self.vel_y[start..end]);
-    }
-
-    fn iter_as_scalar(&self) -> impl Iterator<Item = Boid> + '_ {
-        self.pos_x
-            .iter()
-            .zip(self.pos_y.iter())
-            .zip(self.vel_x.iter().zip(self.vel_y.iter()))
-            .map(|((&pos_x, &pos_y), (&vel_x, &vel_y))| Boid {
-                position: Vec2::new(pos_x, pos_y),
-                velocity: Vec2::new(vel_x, vel_y),
-            })
-    }
-
-    fn len(&self) -> usize {
-        self.pos_x.len()
-    }
-
-    fn num_chunks(&self) -> usize {
-        self.pos_x.len() / CHUNK_SIZE
-    }
-}
-
-#[derive(Debug, Clone, Copy, Default)]
-struct Boid {
-    position: Vec2,
-    velocity: Vec2,
-}
-
-impl Boid {
-    fn new(position: Vec2, velocity: Vec2) -> Self {
-        Boid { position, velocity }
-    }
-
-    fn draw(&self, canvas: &mut graphics::Canvas, boid_mesh: &graphics::Mesh) -> GameResult {
-        let angle = self.velocity.y.atan2(self.velocity.x);
-        canvas.draw(
-            boid_mesh,
-            graphics::DrawParam::new()
-                .dest(self.position)
-                .rotation(angle),
-        );
-        Ok(())
-    }
-}
-
-struct BoidsDoubleBuffer {
-    boids: [UnsafeCell<BoidsVec>; 2],
-    current_idx: usize,
-}
-
-impl BoidsDoubleBuffer {
-    fn new(active_boids: Vec<Boid>) -> Self {
-        let len = active_boids.len();
-        BoidsDoubleBuffer {
-            boids: [
-                UnsafeCell::new(BoidsVec::new_from_scalar(&active_boids)),
-                UnsafeCell::new(BoidsVec::new_with_length(len)),
-            ],
-            current_idx: 0,
-        }
-    }
-
-    fn get_current_boids(&self) -> &BoidsVec {
-        unsafe { &*self.boids[self.current_idx].get() }
-    }
-
-    fn get_next_boids(&self) -> &mut BoidsVec {
-        unsafe { &mut *self.boids[self.current_idx ^ 1].get() }
-    }
-
-    fn swap(&mut self) {
-        self.current_idx ^= 1;
-    }
-}
-
-unsafe impl Sync for BoidsDoubleBuffer {}
+#[cfg(not(portable_simd))]
+pub(crate) use crate::scalar_flock::*;
+#[cfg(portable_simd)]
+pub(crate) use crate::simd_flock::*;
 
 pub struct MainState {
-    boids: BoidsDoubleBuffer,
+    boids: Flock,
     is_attracted: bool,
     rect_max: Vec2,
+    vsync: bool,
+    idle: IdleThrottle,
+    palette: Palette,
+    instruments: Instruments,
+    show_help: bool,
+    checksum: Option<StateChecksum>,
+    history: Option<FrameHistory>,
+    hitch_watch: Option<HitchWatch>,
+    speed_histogram: Option<SpeedHistogram>,
+    energy: Option<EnergyMeter>,
+    sim_steps: u32,
+    clamp_dt: bool,
+    faults: Option<FaultReport>,
+    boid_mesh: graphics::Mesh,
+    snapshot: SimSnapshot,
+    boid_instances: graphics::InstanceArray,
 }
 
 impl MainState {
-    pub fn new(num_boids: u16, rect_max: Vec2) -> GameResult<MainState> {
-        let mut rng = rand_chacha::ChaCha8Rng::from_seed([0; 32]);
+    pub fn new(ctx: &Context, config: &Config, rect_max: Vec2) -> GameResult<MainState> {
+        let mut rng = seeded_rng(config.seed);
+        let num_boids = usize::from(config.num_boids);
         let mut active_boids = vec![];
-        for _ in 0..num_boids {
-            active_boids.push(Self::new_random_boid(rect_max, &mut rng));
+        for boid_idx in 0..num_boids {
+            let position = config
+                .spawn
+                .position(boid_idx, num_boids, rect_max, &mut rng);
+            active_boids.push(Self::new_random_boid(position, &mut rng));
+        }
+        let mut boids = Flock::new(&active_boids, config.extra_work, &config.options);
+        let mut boid_instances = graphics::InstanceArray::new(ctx, None);
+        let mut snapshot = SimSnapshot::new(config.palette.boid);
+        if config.prefault {
+            rayon::broadcast(|_| ());
+            boids.prefault();
+            boid_instances.resize(ctx, num_boids);
+            snapshot.resize(num_boids);
         }
         Ok(MainState {
-            boids: BoidsDoubleBuffer::new(active_boids),
+            boids,
             is_attracted: false,
             rect_max,
+            vsync: config.vsync,
+            idle: IdleThrottle::new(config.idle_fps),
+            palette: config.palette,
+            instruments: Instruments::new(),
+            show_help: false,
+            checksum: config
+                .checksum
+                .then(|| StateChecksum::new(config.checksum_log)),
+            history: (config.history_minutes > 0.0)
+                .then(|| FrameHistory::new(Duration::from_secs_f32(config.history_minutes * 60.0))),
+            hitch_watch: (config.hitch_factor > 0.0).then(|| HitchWatch::new(config.hitch_factor)),
+            speed_histogram: config.speed_histogram.clone().map(SpeedHistogram::new),
+            energy: config.energy.then(EnergyMeter::open).flatten(),
+            sim_steps: config.sim_steps_per_frame,
+            clamp_dt: config.clamp_dt,
+            boid_mesh: make_boid_mesh(ctx)?,
+            faults: None,
+            snapshot,
+            boid_instances,
         })
     }
 
-    fn new_random_boid(rect_max: Vec2, rng: &mut rand_chacha::ChaCha8Rng) -> Boid {
+    pub fn report_page_faults(&mut self, faults: FaultReport) {
+        self.faults = Some(faults);
+    }
+
+    fn new_random_boid(position: Vec2, rng: &mut rand_chacha::ChaCha8Rng) -> Boid {
         let new_boid = |position: Vec2, vel_angle: f32| {
-            let boid = Boid::new(
+            Boid::new(
                 position,
                 Vec2::new(vel_angle.cos(), vel_angle.sin()) * MAX_SPEED / 2.0,
-            );
-            boid
+            )
         };
 
-        new_boid(
-            Vec2::new(
-                rng.gen_range(0.0..rect_max.x),
-                rng.gen_range(0.0..rect_max.y),
-            ),
-            rng.gen_range(0.0..std::f32::consts::TAU),
-        )
+        new_boid(position, rng.gen_range(0.0..std::f32::consts::TAU))
+    }
+
+    const ACTIONS: &'static [Action] =
+        &[Action::ToggleHelp, Action::ToggleVsync, Action::DumpHistory];
+
+    fn apply_action(&mut self, ctx: &Context, action: Action) {
+        match action {
+            Action::ToggleHelp => self.show_help = !self.show_help,
+            Action::ToggleVsync => {
+                self.vsync = !self.vsync;
+                set_vsync(ctx, self.vsync);
+            }
+            Action::DumpHistory => {
+                if let Some(history) = &self.history {
+                    history.dump();
+                }
+            }
+            // The flock controls of the scalar binary have nothing to act on here
+            _ => return,
+        }
+        report_action(action, self.action_state(action));
+    }
+
+    fn action_state(&self, action: Action) -> Option<String> {
+        match action {
+            Action::ToggleVsync => Some(if self.vsync { "on" } else { "off" }.to_string()),
+            Action::DumpHistory => self
+                .history
+                .as_ref()
+                .map(|history| format!("{} frames", history.len())),
+            _ => None,
+        }
     }
 
-    fn make_boid_mesh(&self, ctx: &mut Context) -> GameResult<graphics::Mesh> {
-        let p1 = Vec2::new(BOID_SIZE, 0f32);
-        let p2 = Vec2::new(0f32, BOID_SIZE / 2.0f32);
-        let p3 = Vec2::new(0f32, -BOID_SIZE / 2.0f32);
-        graphics::Mesh::new_polygon(
-            ctx,
-            graphics::DrawMode::fill(),
-            &[p1, p2, p3],
-            if self.is_attracted {
-                Color::BLUE
-            } else {
-                Color::RED
-            },
-        )
+    fn boid_color(&self) -> Color {
+        if self.is_attracted {
+            self.palette.attracted_boid
+        } else {
+            self.palette.boid
+        }
     }
 }
 
 impl EventHandler for MainState {
     fn update(&mut self, ctx: &mut Context) -> GameResult {
+        self.idle.wait();
         tracy_scope!("update");
-        let dt = ctx.time.delta().as_secs_f32();
+        #[cfg(feature = "alloc_check")]
+        alloc_check::frame(self.boids.len());
+        if let Some(history) = &mut self.history {
+            history.record(ctx.time.delta(), self.boids.len());
+        }
+        if let Some(hitch_watch) = &mut self.hitch_watch {
+            if self.idle.is_idle() {
+                hitch_watch.reset();
+            } else if let Some(hitch) = hitch_watch.record(ctx.time.delta()) {
+                hitch.capture(
+                    PATH_NAME,
+                    self.boids.boids().map(|boid| {
+                        [
+                            boid.position.x,
+                            boid.position.y,
+                            boid.velocity.x,
+                            boid.velocity.y,
+                        ]
+                    }),
+                );
+            }
+        }
+        if let Some(action) = pressed_action(ctx) {
+            self.apply_action(ctx, action);
+        }
+
+        let dt = checked_dt(
+            ctx.time.delta().as_secs_f32() / self.sim_steps as f32,
+            self.clamp_dt,
+        );
         // let mouse_pos = Vec2::new(ctx.mouse.position().x, ctx.mouse.position().y);
         {
             tracy_scope!("update_boids");
-            #[cfg(not(feature = "threaded"))]
-            {
-                let current_boids = self.boids.get_current_boids();
-                let next_boids = self.boids.get_next_boids();
-                for chunk_idx in 0..current_boids.num_chunks() {
-                    next_boids.update(chunk_idx, dt, current_boids, self.rect_max);
-                }
-            }
-            #[cfg(feature = "threaded")]
-            {
-                let num_chunks = self.boids.get_current_boids().num_chunks();
-                (0..num_chunks)
-                    .into_par_iter()
-                    .with_min_len(8)
-                    .for_each(|chunk_idx| {
-                        tracy_scope!("update_boids_thread");
-                        self.boids.get_next_boids().update(
-                            chunk_idx,
-                            dt,
-                            self.boids.get_current_boids(),
-                            self.rect_max,
-                        );
-                    });
+            #[cfg(feature = "bandwidth")]
+            let step_start = std::time::Instant::now();
+            for _ in 0..self.sim_steps {
+                self.boids.step(dt, self.rect_max);
             }
+            #[cfg(feature = "bandwidth")]
+            self.instruments.bandwidth.take_frame(
+                self.boids.len(),
+                NEIGHBOR_BYTES,
+                WRITTEN_BYTES,
+                step_start.elapsed() / self.sim_steps,
+            );
+        }
 
-            self.boids.swap();
+        if let Some(energy) = &mut self.energy {
+            energy.frame_done(self.sim_steps);
         }
+
+        if let Some(checksum) = &mut self.checksum {
+            checksum.update(self.boids.boids().flat_map(|boid| {
+                [
+                    boid.position.x,
+                    boid.position.y,
+                    boid.velocity.x,
+                    boid.velocity.y,
+                ]
+            }));
+        }
+
+        if let Some(speed_histogram) = &mut self.speed_histogram {
+            if speed_histogram.is_due() {
+                speed_histogram.record(PATH_NAME, self.boids.boids().map(|boid| boid.velocity));
+            }
+        }
+
+        self.instruments.take_frame();
+
+        let color = self.boid_color();
+        alloc_free!(
+            "snapshot",
+            self.snapshot.capture(
+                self.boids
+                    .boids()
+                    .map(|boid| (boid.position, boid.velocity)),
+                color,
+            )
+        );
         Ok(())
     }
 
     fn draw(&mut self, ctx: &mut Context) -> GameResult {
         tracy_scope!("draw");
-        let mut canvas = graphics::Canvas::from_frame(ctx, Color::WHITE);
+        let mut canvas = graphics::Canvas::from_frame(ctx, self.palette.background);
+        canvas.set_screen_coordinates(graphics::Rect::new(
+            0.0,
+            0.0,
+            self.rect_max.x,
+            self.rect_max.y,
+        ));
         {
             tracy_scope!("draw_boids");
-            let boid_mesh = self.make_boid_mesh(ctx)?;
-            let current_boids = self.boids.get_current_boids();
-            for boid in current_boids.iter_as_scalar() {
-                tracy_scope!("draw_boids");
-                boid.draw(&mut canvas, &boid_mesh)?;
-            }
+            alloc_free!(
+                "draw_boids",
+                self.boid_instances.set(self.snapshot.draw_params())
+            );
+            canvas.draw_instanced_mesh(
+                self.boid_mesh.clone(),
+                &self.boid_instances,
+                DrawParam::new().color(self.snapshot.color),
+            );
         }
 
         {
             tracy_scope!("draw_ui");
-            let fps_text = Text::new(format!("FPS: {:.2}", ctx.time.fps()));
-            canvas.draw(
-                &fps_text,
-                DrawParam::new()
-                    .dest(Vec2::new(10.0, 10.0))
-                    .color(Color::BLACK),
-            );
+            Overlay {
+                sim_steps: self.sim_steps,
+                step_text: "",
+                vsync: self.vsync,
+                paused: false,
+                num_boids: self.boids.len(),
+                checksum: self.checksum.as_ref(),
+                instruments: &self.instruments,
+                energy: self.energy.as_ref(),
+                help: self.show_help.then(|| {
+                    help_text(
+                        |action| Self::ACTIONS.contains(&action),
+                        |action| self.action_state(action),
+                    )
+                }),
+            }
+            .draw(ctx, &mut canvas, &self.palette, self.rect_max);
+        }
 
-            let frametime_text = Text::new(format!(
-                "Frame time: {:.2} us",
-                ctx.time.delta().as_micros()
-            ));
-            canvas.draw(
-                &frametime_text,
-                DrawParam::new()
-                    .dest(Vec2::new(10.0, 20.0))
-                    .color(Color::BLACK),
-            );
+        canvas.finish(ctx)?;
 
-            let boid_count_text =
-                Text::new(format!("Boids: {}", self.boids.get_current_boids().len()));
-            canvas.draw(
-                &boid_count_text,
-                DrawParam::new()
-                    .dest(Vec2::new(10.0, 50.0))
-                    .color(Color::BLACK),
-            );
+        perf_instrument::frame_mark();
+        if let Some(faults) = &mut self.faults {
+            faults.frame_done();
         }
+        Ok(())
+    }
 
-        canvas.finish(ctx)?;
+    fn resize_event(&mut self, ctx: &mut Context, width: f32, height: f32) -> GameResult {
+        self.idle.set_window_size(width, height);
+        set_vsync(ctx, self.vsync);
+        Ok(())
+    }
 
-        tracy_client::frame_mark();
+    fn focus_event(&mut self, _ctx: &mut Context, gained: bool) -> GameResult {
+        self.idle.set_focused(gained);
         Ok(())
     }
+
+    fn quit_event(&mut self, _ctx: &mut Context) -> GameResult<bool> {
+        if let Some(energy) = &self.energy {
+            energy.report(PATH_NAME);
+        }
+        if let Some(speed_histogram) = &self.speed_histogram {
+            speed_histogram.write();
+        }
+        Ok(false)
+    }
 }
@@ -0,0 +1,70 @@
+use boids_common::{next_value, Options};
+
+/// The shared command line options plus the ones of this binary.
+pub type Config = boids_common::Config<SimdOptions>;
+
+/// Lane counts the SIMD kernels are built for.
+pub const SIMD_WIDTHS: [usize; 3] = [4, 8, 16];
+/// Neighbor chunks per round of the kernels' inner loop.
+pub const UNROLLS: [usize; 3] = [1, 2, 4];
+
+/// The shape of the SIMD kernels and the backing of their columns. The scalar fallback has
+/// neither lanes nor columns and ignores both.
+#[derive(Clone)]
+pub struct SimdOptions {
+    /// Boids per chunk, one per lane.
+    pub width: usize,
+    /// Other chunks visited per round, spelled out in the loop body.
+    pub unroll: usize,
+    /// Back the position and velocity columns with transparent huge pages.
+    pub huge_pages: bool,
+}
+
+impl Default for SimdOptions {
+    fn default() -> Self {
+        SimdOptions {
+            width: 8,
+            unroll: 1,
+            huge_pages: false,
+        }
+    }
+}
+
+impl Options for SimdOptions {
+    fn parse_arg(&mut self, arg: &str, args: &mut dyn Iterator<Item = String>) -> bool {
+        let (choices, value) = match arg {
+            "--huge-pages" => {
+                self.huge_pages = true;
+                return true;
+            }
+            "--simd-width" => (&SIMD_WIDTHS, &mut self.width),
+            "--unroll" => (&UNROLLS, &mut self.unroll),
+            _ => return false,
+        };
+        match next_value::<usize>(args, arg) {
+            Some(choice) if choices.contains(&choice) => *value = choice,
+            Some(_) => eprintln!("Expected one of {choices:?} for `{arg}`, keeping {value}"),
+            None => {}
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Config {
+        Config::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn kernel_shape_must_be_one_that_is_built() {
+        let config = parse(&["--simd-width", "16", "--unroll", "4", "800"]);
+        assert_eq!((config.options.width, config.options.unroll), (16, 4));
+        assert_eq!(config.num_boids, 800);
+        let config = parse(&["--simd-width", "12", "--unroll", "0", "--huge-pages"]);
+        assert_eq!((config.options.width, config.options.unroll), (8, 1));
+        assert!(config.options.huge_pages);
+    }
+}
@@ -0,0 +1,27 @@
+//! The SIMD flock, or the scalar fallback on stable toolchains, and the frame loop around it. They
+//! are a library so that boids-bench can step the flock without a window.
+#![cfg_attr(portable_simd, feature(portable_simd))]
+
+pub mod boids_impl;
+pub mod config;
+#[cfg(not(portable_simd))]
+pub mod scalar_flock;
+#[cfg(portable_simd)]
+pub mod simd_flock;
+
+#[cfg(not(portable_simd))]
+pub use scalar_flock::{Flock, PATH_NAME, SIMD_PATH};
+#[cfg(portable_simd)]
+pub use simd_flock::{Flock, PATH_NAME, SIMD_PATH};
+
+/// The flock's path and, for the SIMD kernels, their chunk shape, as printed at startup.
+#[cfg_attr(not(portable_simd), allow(unused_variables))]
+pub fn path_description(options: &config::SimdOptions) -> String {
+    let description = format!("{PATH_NAME} ({SIMD_PATH})");
+    #[cfg(portable_simd)]
+    let description = format!(
+        "{description}, f32x{} chunks, unrolled {} times",
+        options.width, options.unroll
+    );
+    description
+}
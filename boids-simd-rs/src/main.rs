@@ -1,15 +1,33 @@
-#![feature(portable_simd)]
+use boids_simd_rs::{boids_impl, config};
 use ggez::event::{self};
 use ggez::{ContextBuilder, GameResult};
 use glam::Vec2;
-mod boids_impl;
 
 type MainState = boids_impl::MainState;
 
+#[cfg(feature = "alloc_check")]
+#[global_allocator]
+static ALLOCATOR: boids_common::alloc_check::CountingAlloc =
+    boids_common::alloc_check::CountingAlloc;
+
 fn main() -> GameResult {
-    tracy_client::Client::start();
+    perf_instrument::start();
+
+    let config = config::Config::from_args();
+    eprintln!(
+        "SIMD path: {}",
+        boids_simd_rs::path_description(&config.options)
+    );
+    boids_common::report_extra_work(config.extra_work);
+    if config.thread_priority {
+        boids_common::configure_threads(true, 0);
+    }
 
-    let num_boids: u16 = 4000;
+    let mut faults = if config.page_faults {
+        boids_common::FaultReport::start()
+    } else {
+        None
+    };
 
     let dim_x = 1080.0;
     let dim_y = 800.0;
@@ -17,11 +35,26 @@ fn main() -> GameResult {
         .window_setup(
             ggez::conf::WindowSetup::default()
                 .title("Boids")
-                .vsync(false),
+                .vsync(config.vsync),
         )
-        .window_mode(ggez::conf::WindowMode::default().dimensions(dim_x, dim_y))
+        // Sized in logical pixels, so the demo covers the same share of a HiDPI display or a 4K
+        // projector as it does of a plain monitor. The states draw in the same logical space.
+        .window_mode(ggez::conf::WindowMode {
+            logical_size: Some(ggez::winit::dpi::LogicalSize::new(dim_x, dim_y)),
+            ..ggez::conf::WindowMode::default().resize_on_scale_factor_change(true)
+        })
         .build()?;
+    if let Some(faults) = &mut faults {
+        faults.phase("window setup");
+    }
 
-    let state = MainState::new(num_boids, Vec2::new(dim_x, dim_y))?;
+    let mut state = MainState::new(&ctx, &config, Vec2::new(dim_x, dim_y))?;
+    if let Some(mut faults) = faults {
+        faults.phase("simulation setup");
+        state.report_page_faults(faults);
+    }
+    if config.options.huge_pages {
+        boids_common::report_huge_pages("simulation setup");
+    }
     event::run(ctx, event_loop, state)
 }
@@ -0,0 +1,125 @@
+//! The flock on toolchains without `portable_simd`: the packed boids and rules of the threaded
+//! boids-rs implementation, one boid at a time. The demo still runs on stable, only without the
+//! kernels it is about.
+
+use boids_common::scalar::Boid;
+use boids_common::*;
+use glam::Vec2;
+#[cfg(feature = "threaded")]
+use rayon::prelude::*;
+
+use crate::config::SimdOptions;
+
+/// Which flock the binary was built with, `build.rs` picks the SIMD one on nightly.
+pub const SIMD_PATH: &str = "scalar fallback, no portable_simd on this toolchain";
+/// Labels the flock in the energy report and hitch captures.
+pub const PATH_NAME: &str = "scalar_fallback";
+
+/// Every boid reads every other boid once per rule, from a packed buffer.
+#[cfg(feature = "bandwidth")]
+pub const NEIGHBOR_BYTES: usize = 3 * std::mem::size_of::<Boid>();
+#[cfg(feature = "bandwidth")]
+pub const WRITTEN_BYTES: usize = std::mem::size_of::<Boid>();
+
+/// The flock behind the frame loop, double buffered so a step reads one set of boids and writes
+/// the other.
+pub struct Flock {
+    current: Vec<Boid>,
+    /// Only reserved until `prefault` or the first step writes it.
+    next: Vec<Boid>,
+    /// Hash rounds per boid, from `--extra-work`.
+    extra_work: u32,
+}
+
+impl Flock {
+    /// `extra_work` is in rounds per boid, as `--extra-work` gives it. The kernel shape in
+    /// `_options` has nothing to pick here.
+    pub fn new(boids: &[Boid], extra_work: u32, _options: &SimdOptions) -> Self {
+        Flock {
+            current: boids.to_vec(),
+            next: Vec::with_capacity(boids.len()),
+            extra_work,
+        }
+    }
+
+    // Filling the reserved next buffer from the pool puts each page's first touch on a thread
+    // that steps it, instead of in the first update.
+    pub fn prefault(&mut self) {
+        use rayon::prelude::*;
+
+        self.next.par_extend(self.current.par_iter().copied());
+    }
+
+    pub fn len(&self) -> usize {
+        self.current.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn boids(&self) -> impl Iterator<Item = Boid> + '_ {
+        self.current.iter().copied()
+    }
+
+    pub fn step(&mut self, dt: f32, rect_max: Vec2) {
+        let (current_boids, rounds) = (&self.current, self.extra_work);
+        let stepped = |boid_idx: usize| {
+            let boid = &current_boids[boid_idx];
+            let acceleration = boid.calc_acceleration(boid_idx, current_boids, &[], 0.0);
+            extra_work(boid_idx, rounds);
+            let mut next_boid = Boid::default();
+            next_boid.update(dt, boid, acceleration);
+            next_boid.edges(rect_max.x, rect_max.y);
+            next_boid
+        };
+        // Both extends write into the capacity left over from the previous step
+        self.next.clear();
+        #[cfg(not(feature = "threaded"))]
+        alloc_free!(
+            "update_boids",
+            self.next.extend((0..current_boids.len()).map(stepped))
+        );
+        #[cfg(feature = "threaded")]
+        alloc_free!(
+            pool "update_boids",
+            self.next.par_extend(
+                (0..current_boids.len())
+                    .into_par_iter()
+                    .with_min_len(64)
+                    .map(stepped)
+            )
+        );
+        std::mem::swap(&mut self.current, &mut self.next);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use boids_common::test_support;
+
+    use super::*;
+
+    #[test]
+    fn step_matches_the_shared_rules() {
+        let rect_max = Vec2::new(1080.0, 800.0);
+        let center = rect_max / 2.0;
+        let boids: Vec<Boid> = test_support::ring(13, 40.0, 10.0)
+            .map(|(position, velocity)| Boid::new(position + center, velocity))
+            .collect();
+        let mut flock = Flock::new(&boids, 0, &SimdOptions::default());
+        flock.step(0.01, rect_max);
+        assert_eq!(flock.len(), boids.len());
+        for (boid_idx, (boid, stepped)) in boids.iter().zip(flock.boids()).enumerate() {
+            let mut expected = Boid::default();
+            expected.update(
+                0.01,
+                boid,
+                boid.calc_acceleration(boid_idx, &boids, &[], 0.0),
+            );
+            expected.edges(rect_max.x, rect_max.y);
+            assert_eq!(stepped.position, expected.position, "boid {boid_idx}");
+            assert_eq!(stepped.velocity, expected.velocity, "boid {boid_idx}");
+        }
+    }
+}
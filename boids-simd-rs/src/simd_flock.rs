@@ -0,0 +1,859 @@
+//! The SIMD flock: the boids in `f32` columns, each rule worked out for a chunk of `N` lanes
+//! against every other chunk, `UNROLL` chunks at a time. `--simd-width` and `--unroll` pick the
+//! pair at startup. Only built on toolchains with `portable_simd`, see `build.rs`.
+
+use std::cell::UnsafeCell;
+use std::simd::cmp::{SimdPartialEq, SimdPartialOrd};
+#[cfg(feature = "horizontal")]
+use std::simd::num::SimdFloat;
+use std::simd::{Mask, Select, Simd, StdFloat};
+
+use boids_common::scalar::Boid;
+use boids_common::*;
+use glam::Vec2;
+#[cfg(feature = "threaded")]
+use perf_instrument::tracy_scope;
+#[cfg(feature = "threaded")]
+use rayon::prelude::*;
+use seq_macro::seq;
+
+use crate::config::SimdOptions;
+
+/// Which flock the binary was built with, `build.rs` picks the SIMD one on nightly.
+pub const SIMD_PATH: &str = "portable_simd";
+/// Labels the flock in the energy report and hitch captures.
+pub const PATH_NAME: &str = "simd";
+
+const EPSILON: f32 = 0.0001;
+
+// The lane rotations and the unrolled chunk walk are spelled out up to these bounds, a chunk
+// wider or a round longer than that would not fit the `seq!` ranges below.
+const MAX_CHUNK_SIZE: usize = 16;
+const MAX_UNROLL: usize = 4;
+
+#[derive(Debug, Clone, Copy)]
+struct SimdVec2<const N: usize> {
+    x: Simd<f32, N>,
+    y: Simd<f32, N>,
+}
+
+#[cfg_attr(feature = "horizontal", allow(dead_code))]
+impl<const N: usize> SimdVec2<N> {
+    fn new_splat_all(v: f32) -> Self {
+        SimdVec2 {
+            x: Simd::splat(v),
+            y: Simd::splat(v),
+        }
+    }
+
+    fn zero() -> Self {
+        SimdVec2 {
+            x: Simd::splat(0.0),
+            y: Simd::splat(0.0),
+        }
+    }
+
+    fn new(x: Simd<f32, N>, y: Simd<f32, N>) -> Self {
+        SimdVec2 { x, y }
+    }
+
+    #[cfg(feature = "horizontal")]
+    fn splat(v: Vec2) -> Self {
+        SimdVec2 {
+            x: Simd::splat(v.x),
+            y: Simd::splat(v.y),
+        }
+    }
+
+    #[cfg(feature = "horizontal")]
+    fn reduce_sum(&self) -> Vec2 {
+        Vec2::new(self.x.reduce_sum(), self.y.reduce_sum())
+    }
+
+    fn normalize(&self) -> Self {
+        let length = self.length();
+        let x = self.x / length;
+        let y = self.y / length;
+        SimdVec2 { x, y }
+    }
+
+    fn select(&self, mask: MaskType<N>, other: Self) -> Self {
+        let x = mask.select(self.x, other.x);
+        let y = mask.select(self.y, other.y);
+        SimdVec2 { x, y }
+    }
+
+    fn length(&self) -> Simd<f32, N> {
+        (self.x * self.x + self.y * self.y).sqrt()
+    }
+
+    fn length_squared(&self) -> Simd<f32, N> {
+        self.x * self.x + self.y * self.y
+    }
+
+    fn clamp_length_max(&self, max: f32) -> Self {
+        let length_sqr = self.length_squared();
+        let max_simd = Simd::splat(max);
+        let mask = length_sqr.simd_gt(max_simd * max_simd);
+        count_diagnostic!(clamped, mask.to_bitmask().count_ones());
+        let x = mask.select(max_simd * (self.x / length_sqr.sqrt()), self.x);
+        let y = mask.select(max_simd * (self.y / length_sqr.sqrt()), self.y);
+        SimdVec2 { x, y }
+    }
+
+    fn rotate_elements_right<const OFFSET: usize>(&self) -> Self {
+        let x = self.x.rotate_elements_right::<OFFSET>();
+        let y = self.y.rotate_elements_right::<OFFSET>();
+        SimdVec2 { x, y }
+    }
+}
+
+impl<const N: usize> std::ops::Add for SimdVec2<N> {
+    type Output = Self;
+    fn add(self, rhs: Self) -> Self {
+        SimdVec2::new(self.x + rhs.x, self.y + rhs.y)
+    }
+}
+
+impl<const N: usize> std::ops::Sub for SimdVec2<N> {
+    type Output = Self;
+    fn sub(self, rhs: Self) -> Self {
+        SimdVec2::new(self.x - rhs.x, self.y - rhs.y)
+    }
+}
+
+impl<const N: usize> std::ops::Mul<Simd<f32, N>> for SimdVec2<N> {
+    type Output = Self;
+    fn mul(self, rhs: Simd<f32, N>) -> Self {
+        SimdVec2::new(self.x * rhs, self.y * rhs)
+    }
+}
+
+impl<const N: usize> std::ops::Div<Simd<f32, N>> for SimdVec2<N> {
+    type Output = Self;
+    fn div(self, rhs: Simd<f32, N>) -> Self {
+        SimdVec2::new(self.x / rhs, self.y / rhs)
+    }
+}
+
+impl<const N: usize> std::ops::AddAssign for SimdVec2<N> {
+    fn add_assign(&mut self, rhs: Self) {
+        self.x += rhs.x;
+        self.y += rhs.y;
+    }
+}
+
+type MaskType<const N: usize> = Mask<i32, N>;
+fn simd_is_close_enough<const N: usize>(
+    lhs: &SimdVec2<N>,
+    rhs: &SimdVec2<N>,
+    max_dist: f32,
+) -> MaskType<N> {
+    let distance_squared = (*lhs - *rhs).length_squared();
+    let simd_dist_sqr = Simd::splat(max_dist * max_dist);
+    distance_squared.simd_le(simd_dist_sqr)
+}
+
+/// Drops neighbors sitting on top of the boid, which have no direction to steer by.
+fn simd_epsilon_check<const N: usize>(lhs: &SimdVec2<N>, rhs: &SimdVec2<N>) -> MaskType<N> {
+    let distance_squared = (*lhs - *rhs).length_squared();
+    let simd_epsilon = Simd::splat(EPSILON * EPSILON);
+    distance_squared.simd_gt(simd_epsilon)
+}
+
+/// Drops the lane of the boid itself when `chunk_idx` is the chunk it sits in.
+#[cfg(feature = "horizontal")]
+fn simd_not_self<const N: usize>(boid_idx: usize, chunk_idx: usize) -> MaskType<N> {
+    let self_lane = if boid_idx / N == chunk_idx {
+        boid_idx % N
+    } else {
+        N
+    };
+    let lanes = Simd::<u32, N>::from_array(std::array::from_fn(|lane| lane as u32));
+    lanes.simd_ne(Simd::splat(self_lane as u32))
+}
+
+/// The boids as columns, stepped in chunks of `N` lanes that each walk the other chunks
+/// `UNROLL` at a time.
+struct BoidsVec<const N: usize, const UNROLL: usize> {
+    pos_x: Column,
+    pos_y: Column,
+    vel_x: Column,
+    vel_y: Column,
+}
+
+// The vertical kernels are unused when the horizontal ones are compiled in
+#[cfg_attr(feature = "horizontal", allow(dead_code))]
+impl<const N: usize, const UNROLL: usize> BoidsVec<N, UNROLL> {
+    fn new_from_scalar(scalar_vec: &[Boid], huge_pages: bool) -> Self {
+        let mut boids = Self::new_with_length(scalar_vec.len(), huge_pages);
+        for (boid_idx, boid) in scalar_vec.iter().enumerate() {
+            boids.pos_x[boid_idx] = boid.position.x;
+            boids.pos_y[boid_idx] = boid.position.y;
+            boids.vel_x[boid_idx] = boid.velocity.x;
+            boids.vel_y[boid_idx] = boid.velocity.y;
+        }
+        boids
+    }
+
+    fn new_with_length(len: usize, huge_pages: bool) -> Self {
+        BoidsVec {
+            pos_x: Column::zeroed(len, huge_pages),
+            pos_y: Column::zeroed(len, huge_pages),
+            vel_x: Column::zeroed(len, huge_pages),
+            vel_y: Column::zeroed(len, huge_pages),
+        }
+    }
+
+    #[inline(always)]
+    fn whole_boids_at(&self, chunk_idx: usize) -> (SimdVec2<N>, SimdVec2<N>) {
+        let start = chunk_idx * N;
+        let end = start + N;
+        let pos = SimdVec2::new(
+            Simd::from_slice(&self.pos_x[start..end]),
+            Simd::from_slice(&self.pos_y[start..end]),
+        );
+        let vel = SimdVec2::new(
+            Simd::from_slice(&self.vel_x[start..end]),
+            Simd::from_slice(&self.vel_y[start..end]),
+        );
+        (pos, vel)
+    }
+
+    #[inline(always)]
+    fn boids_pos_at(&self, chunk_idx: usize) -> SimdVec2<N> {
+        let start = chunk_idx * N;
+        let end = start + N;
+        SimdVec2::new(
+            Simd::from_slice(&self.pos_x[start..end]),
+            Simd::from_slice(&self.pos_y[start..end]),
+        )
+    }
+
+    // Walks the chunks `UNROLL` at a time, with the visits of a round spelled out so that each
+    // one keeps its own registers. The chunks past the last full round are visited one by one.
+    #[inline(always)]
+    #[allow(clippy::identity_op)]
+    fn for_chunks(&self, mut visit: impl FnMut(usize)) {
+        const { assert!(UNROLL >= 1 && UNROLL <= MAX_UNROLL) };
+        let num_chunks = self.num_chunks();
+        let unrolled = num_chunks - num_chunks % UNROLL;
+        for round in (0..unrolled).step_by(UNROLL) {
+            seq!(STEP in 0..4 {
+                if STEP < UNROLL {
+                    visit(round + STEP);
+                }
+            });
+        }
+        (unrolled..num_chunks).for_each(visit);
+    }
+
+    #[inline(always)]
+    fn for_other_chunks(&self, chunk_idx: usize, mut visit: impl FnMut(usize)) {
+        self.for_chunks(|other_chunk_idx| {
+            if other_chunk_idx != chunk_idx {
+                visit(other_chunk_idx);
+            }
+        });
+    }
+
+    // Each rule first pairs the chunk with itself over lane rotations 1..N only, since rotation 0
+    // would pair every lane with itself. The rotations are spelled out up to `MAX_CHUNK_SIZE` and
+    // the ones past `N` compile to nothing. That leaves the epsilon test to guard against coincident
+    // neighbors, which the scalar rules drop as well, and keeps a boid's own lane out of the
+    // epsilon guard diagnostics.
+    #[inline(always)]
+    fn alignment_for_permutation<const PERM: usize>(
+        alignment: &mut SimdVec2<N>,
+        total: &mut Simd<f32, N>,
+        this_pos: &SimdVec2<N>,
+        other_pos: &SimdVec2<N>,
+        other_vel: &SimdVec2<N>,
+    ) {
+        const { assert!(N <= MAX_CHUNK_SIZE) };
+        if PERM >= N {
+            return;
+        }
+        let other_pos = other_pos.rotate_elements_right::<PERM>();
+        let other_vel = other_vel.rotate_elements_right::<PERM>();
+        let is_close_mask = simd_is_close_enough(this_pos, &other_pos, PERCEPTION);
+        let epsilon_mask = simd_epsilon_check(this_pos, &other_pos);
+        count_diagnostic!(
+            epsilon_guard,
+            (is_close_mask & !epsilon_mask).to_bitmask().count_ones()
+        );
+        let mask = is_close_mask & epsilon_mask;
+        let one_or_zero = mask.select(Simd::splat(1.0), Simd::splat(0.0));
+        *alignment += other_vel * one_or_zero;
+        *total += one_or_zero;
+    }
+
+    #[cfg_attr(not(feature = "inline_default"), inline(never))]
+    #[cfg_attr(feature = "inline_always", inline(always))]
+    fn alignment(&self, chunk_idx: usize) -> SimdVec2<N> {
+        let mut alignment: SimdVec2<N> = SimdVec2::new_splat_all(0.0);
+        let mut total: Simd<f32, N> = Simd::splat(0.0);
+
+        let (this_pos, this_vel) = self.whole_boids_at(chunk_idx);
+        seq!(PERM in 1..16 {
+            Self::alignment_for_permutation::<PERM>(
+                &mut alignment,
+                &mut total,
+                &this_pos,
+                &this_pos,
+                &this_vel,
+            );
+        });
+        self.for_other_chunks(chunk_idx, |other_chunk_idx| {
+            let (other_pos, other_vel) = self.whole_boids_at(other_chunk_idx);
+            seq!(PERM in 0..16 {
+                Self::alignment_for_permutation::<PERM>(
+                    &mut alignment,
+                    &mut total,
+                    &this_pos,
+                    &other_pos,
+                    &other_vel,
+                );
+            });
+        });
+
+        let total_mask = total.simd_ne(Simd::splat(0.0));
+        count_diagnostic!(zero_neighbors, (!total_mask).to_bitmask().count_ones());
+        alignment = alignment / total;
+        alignment = alignment.normalize() * Simd::splat(MAX_SPEED);
+        alignment = alignment - this_vel;
+        alignment = alignment.clamp_length_max(MAX_FORCE);
+        alignment.select(total_mask, SimdVec2::zero())
+    }
+
+    #[inline(always)]
+    fn cohesion_for_permutation<const PERM: usize>(
+        cohesion: &mut SimdVec2<N>,
+        total: &mut Simd<f32, N>,
+        this_pos: &SimdVec2<N>,
+        other_pos: &SimdVec2<N>,
+    ) {
+        if PERM >= N {
+            return;
+        }
+        let other_pos = other_pos.rotate_elements_right::<PERM>();
+        let is_close_mask = simd_is_close_enough(this_pos, &other_pos, PERCEPTION);
+        let epsilon_mask = simd_epsilon_check(this_pos, &other_pos);
+        count_diagnostic!(
+            epsilon_guard,
+            (is_close_mask & !epsilon_mask).to_bitmask().count_ones()
+        );
+        let mask = is_close_mask & epsilon_mask;
+        let one_or_zero = mask.select(Simd::splat(1.0), Simd::splat(0.0));
+        *cohesion += other_pos * one_or_zero;
+        *total += one_or_zero;
+    }
+
+    #[cfg_attr(not(feature = "inline_default"), inline(never))]
+    #[cfg_attr(feature = "inline_always", inline(always))]
+    fn cohesion(&self, chunk_idx: usize) -> SimdVec2<N> {
+        let mut cohesion: SimdVec2<N> = SimdVec2::new_splat_all(0.0);
+        let mut total: Simd<f32, N> = Simd::splat(0.0);
+
+        let (this_pos, this_vel) = self.whole_boids_at(chunk_idx);
+        seq!(PERM in 1..16 {
+            Self::cohesion_for_permutation::<PERM>(&mut cohesion, &mut total, &this_pos, &this_pos);
+        });
+        self.for_other_chunks(chunk_idx, |other_chunk_idx| {
+            let other_pos = self.boids_pos_at(other_chunk_idx);
+            seq!(PERM in 0..16 {
+                Self::cohesion_for_permutation::<PERM>(
+                    &mut cohesion,
+                    &mut total,
+                    &this_pos,
+                    &other_pos,
+                );
+            });
+        });
+
+        let total_mask = total.simd_ne(Simd::splat(0.0));
+        count_diagnostic!(zero_neighbors, (!total_mask).to_bitmask().count_ones());
+        cohesion = cohesion / total;
+        cohesion = (cohesion - this_pos).normalize() * Simd::splat(MAX_SPEED);
+        cohesion = cohesion - this_vel;
+        cohesion = cohesion.clamp_length_max(MAX_FORCE);
+        cohesion.select(total_mask, SimdVec2::zero())
+    }
+
+    #[inline(always)]
+    fn separation_for_permutation<const PERM: usize>(
+        separation: &mut SimdVec2<N>,
+        total: &mut Simd<f32, N>,
+        this_pos: &SimdVec2<N>,
+        other_pos: &SimdVec2<N>,
+    ) {
+        if PERM >= N {
+            return;
+        }
+        let other_pos = other_pos.rotate_elements_right::<PERM>();
+        let diff = *this_pos - other_pos;
+        let distance = diff.length();
+        record_distances!(distance.to_array());
+        let is_close_mask = distance.simd_le(Simd::splat(SEPARATION));
+        let epsilon_mask = distance.simd_gt(Simd::splat(EPSILON));
+        count_diagnostic!(
+            epsilon_guard,
+            (is_close_mask & !epsilon_mask).to_bitmask().count_ones()
+        );
+        let mask = is_close_mask & epsilon_mask;
+        let separation_acc =
+            (diff.normalize() / distance).select(mask, SimdVec2::new_splat_all(0.0));
+        *separation += separation_acc;
+        *total += mask.select(Simd::splat(1.0), Simd::splat(0.0));
+    }
+
+    #[cfg_attr(not(feature = "inline_default"), inline(never))]
+    #[cfg_attr(feature = "inline_always", inline(always))]
+    fn separation(&self, chunk_idx: usize) -> SimdVec2<N> {
+        let mut separation: SimdVec2<N> = SimdVec2::new_splat_all(0.0);
+        let mut total: Simd<f32, N> = Simd::splat(0.0);
+
+        let (this_pos, this_vel) = self.whole_boids_at(chunk_idx);
+        seq!(PERM in 1..16 {
+            Self::separation_for_permutation::<PERM>(
+                &mut separation,
+                &mut total,
+                &this_pos,
+                &this_pos,
+            );
+        });
+        self.for_other_chunks(chunk_idx, |other_chunk_idx| {
+            let other_pos = self.boids_pos_at(other_chunk_idx);
+            seq!(PERM in 0..16 {
+                Self::separation_for_permutation::<PERM>(
+                    &mut separation,
+                    &mut total,
+                    &this_pos,
+                    &other_pos,
+                );
+            });
+        });
+
+        let total_mask = total.simd_ne(Simd::splat(0.0));
+        count_diagnostic!(zero_neighbors, (!total_mask).to_bitmask().count_ones());
+        separation = separation / total;
+        separation = separation.normalize() * Simd::splat(MAX_SPEED);
+        separation = separation - this_vel;
+        separation = separation.clamp_length_max(MAX_FORCE);
+        separation.select(total_mask, SimdVec2::zero())
+    }
+
+    #[cfg_attr(not(feature = "inline_default"), inline(never))]
+    #[cfg_attr(feature = "inline_always", inline(always))]
+    fn calc_acceleration(&self, chunk_idx: usize) -> SimdVec2<N> {
+        let alignment = timed_rule!(alignment, self.alignment(chunk_idx));
+        let cohesion = timed_rule!(cohesion, self.cohesion(chunk_idx));
+        let separation = timed_rule!(separation, self.separation(chunk_idx));
+        alignment + cohesion + separation
+    }
+
+    // The horizontal kernels are the transpose of the ones above: a single "self" boid is splatted
+    // across all lanes and tested against N different "other" boids per iteration, and the lanes
+    // are only summed up once at the end. This is the shape that fits per-boid neighbor lists.
+    // The boid meets its own lane in its own chunk, which is masked out by index so that only
+    // coincident neighbors reach the epsilon test.
+    #[cfg(feature = "horizontal")]
+    fn boid_at(&self, boid_idx: usize) -> (Vec2, Vec2) {
+        (
+            Vec2::new(self.pos_x[boid_idx], self.pos_y[boid_idx]),
+            Vec2::new(self.vel_x[boid_idx], self.vel_y[boid_idx]),
+        )
+    }
+
+    #[cfg(feature = "horizontal")]
+    #[cfg_attr(not(feature = "inline_default"), inline(never))]
+    #[cfg_attr(feature = "inline_always", inline(always))]
+    fn alignment_horizontal(&self, boid_idx: usize) -> Vec2 {
+        let (this_pos, this_vel) = self.boid_at(boid_idx);
+        let mut alignment = SimdVec2::zero();
+        let mut total = Simd::<f32, N>::splat(0.0);
+
+        let this_pos_simd = SimdVec2::splat(this_pos);
+        self.for_chunks(|other_chunk_idx| {
+            let (other_pos, other_vel) = self.whole_boids_at(other_chunk_idx);
+            let is_close_mask = simd_is_close_enough(&this_pos_simd, &other_pos, PERCEPTION)
+                & simd_not_self(boid_idx, other_chunk_idx);
+            let epsilon_mask = simd_epsilon_check(&this_pos_simd, &other_pos);
+            count_diagnostic!(
+                epsilon_guard,
+                (is_close_mask & !epsilon_mask).to_bitmask().count_ones()
+            );
+            let mask = is_close_mask & epsilon_mask;
+            let one_or_zero = mask.select(Simd::splat(1.0), Simd::splat(0.0));
+            alignment += other_vel * one_or_zero;
+            total += one_or_zero;
+        });
+
+        let total = total.reduce_sum();
+        count_diagnostic!(zero_neighbors, total == 0.0);
+        if total == 0.0 {
+            return Vec2::ZERO;
+        }
+        let alignment = alignment.reduce_sum() / total;
+        let force = alignment.normalize() * MAX_SPEED - this_vel;
+        count_diagnostic!(clamped, force.length_squared() > MAX_FORCE * MAX_FORCE);
+        force.clamp_length_max(MAX_FORCE)
+    }
+
+    #[cfg(feature = "horizontal")]
+    #[cfg_attr(not(feature = "inline_default"), inline(never))]
+    #[cfg_attr(feature = "inline_always", inline(always))]
+    fn cohesion_horizontal(&self, boid_idx: usize) -> Vec2 {
+        let (this_pos, this_vel) = self.boid_at(boid_idx);
+        let mut cohesion = SimdVec2::zero();
+        let mut total = Simd::<f32, N>::splat(0.0);
+
+        let this_pos_simd = SimdVec2::splat(this_pos);
+        self.for_chunks(|other_chunk_idx| {
+            let other_pos = self.boids_pos_at(other_chunk_idx);
+            let is_close_mask = simd_is_close_enough(&this_pos_simd, &other_pos, PERCEPTION)
+                & simd_not_self(boid_idx, other_chunk_idx);
+            let epsilon_mask = simd_epsilon_check(&this_pos_simd, &other_pos);
+            count_diagnostic!(
+                epsilon_guard,
+                (is_close_mask & !epsilon_mask).to_bitmask().count_ones()
+            );
+            let mask = is_close_mask & epsilon_mask;
+            let one_or_zero = mask.select(Simd::splat(1.0), Simd::splat(0.0));
+            cohesion += other_pos * one_or_zero;
+            total += one_or_zero;
+        });
+
+        let total = total.reduce_sum();
+        count_diagnostic!(zero_neighbors, total == 0.0);
+        if total == 0.0 {
+            return Vec2::ZERO;
+        }
+        let cohesion = cohesion.reduce_sum() / total;
+        let force = (cohesion - this_pos).normalize() * MAX_SPEED - this_vel;
+        count_diagnostic!(clamped, force.length_squared() > MAX_FORCE * MAX_FORCE);
+        force.clamp_length_max(MAX_FORCE)
+    }
+
+    #[cfg(feature = "horizontal")]
+    #[cfg_attr(not(feature = "inline_default"), inline(never))]
+    #[cfg_attr(feature = "inline_always", inline(always))]
+    fn separation_horizontal(&self, boid_idx: usize) -> Vec2 {
+        let (this_pos, this_vel) = self.boid_at(boid_idx);
+        let mut separation = SimdVec2::zero();
+        let mut total = Simd::<f32, N>::splat(0.0);
+
+        let this_pos_simd = SimdVec2::splat(this_pos);
+        self.for_chunks(|other_chunk_idx| {
+            let other_pos = self.boids_pos_at(other_chunk_idx);
+            let diff = this_pos_simd - other_pos;
+            let distance = diff.length();
+            record_distances!(distance.to_array());
+            let is_close_mask = distance.simd_le(Simd::splat(SEPARATION))
+                & simd_not_self(boid_idx, other_chunk_idx);
+            let epsilon_mask = distance.simd_gt(Simd::splat(EPSILON));
+            count_diagnostic!(
+                epsilon_guard,
+                (is_close_mask & !epsilon_mask).to_bitmask().count_ones()
+            );
+            let mask = is_close_mask & epsilon_mask;
+            separation += (diff.normalize() / distance).select(mask, SimdVec2::zero());
+            total += mask.select(Simd::splat(1.0), Simd::splat(0.0));
+        });
+
+        let total = total.reduce_sum();
+        count_diagnostic!(zero_neighbors, total == 0.0);
+        if total == 0.0 {
+            return Vec2::ZERO;
+        }
+        let separation = separation.reduce_sum() / total;
+        let force = separation.normalize() * MAX_SPEED - this_vel;
+        count_diagnostic!(clamped, force.length_squared() > MAX_FORCE * MAX_FORCE);
+        force.clamp_length_max(MAX_FORCE)
+    }
+
+    #[cfg(feature = "horizontal")]
+    #[cfg_attr(not(feature = "inline_default"), inline(never))]
+    #[cfg_attr(feature = "inline_always", inline(always))]
+    fn calc_acceleration_horizontal(&self, chunk_idx: usize) -> SimdVec2<N> {
+        let mut acceleration_x = [0.0; N];
+        let mut acceleration_y = [0.0; N];
+        for lane in 0..N {
+            let boid_idx = chunk_idx * N + lane;
+            let acceleration = timed_rule!(alignment, self.alignment_horizontal(boid_idx))
+                + timed_rule!(cohesion, self.cohesion_horizontal(boid_idx))
+                + timed_rule!(separation, self.separation_horizontal(boid_idx));
+            acceleration_x[lane] = acceleration.x;
+            acceleration_y[lane] = acceleration.y;
+        }
+        SimdVec2::new(
+            Simd::from_array(acceleration_x),
+            Simd::from_array(acceleration_y),
+        )
+    }
+
+    fn update(&mut self, chunk_idx: usize, dt: f32, source: &Self, screen_rect: Vec2) {
+        let start = chunk_idx * N;
+        let end = start + N;
+        let mut this_pos: SimdVec2<N> = SimdVec2::new(
+            Simd::from_slice(&source.pos_x[start..end]),
+            Simd::from_slice(&source.pos_y[start..end]),
+        );
+        let mut this_vel: SimdVec2<N> = SimdVec2::new(
+            Simd::from_slice(&source.vel_x[start..end]),
+            Simd::from_slice(&source.vel_y[start..end]),
+        );
+        #[cfg(not(feature = "horizontal"))]
+        let acceleration: SimdVec2<N> = source.calc_acceleration(chunk_idx);
+        #[cfg(feature = "horizontal")]
+        let acceleration: SimdVec2<N> = source.calc_acceleration_horizontal(chunk_idx);
+
+        let simd_dt = Simd::splat(dt);
+        let this_frame_acceleration = std::hint::black_box(acceleration * simd_dt);
+        #[cfg(feature = "static_update")]
+        let this_frame_acceleration = SimdVec2::new_splat_all(0.0);
+
+        this_vel += this_frame_acceleration;
+
+        let this_frame_velocity = std::hint::black_box(this_vel * simd_dt);
+        #[cfg(feature = "static_update")]
+        let this_frame_velocity = SimdVec2::new_splat_all(0.0);
+
+        this_pos += this_frame_velocity;
+
+        // Edges
+        let mask_x = this_pos.x.simd_gt(Simd::splat(screen_rect.x));
+        let mask_y = this_pos.y.simd_gt(Simd::splat(screen_rect.y));
+        this_pos.x = mask_x.select(Simd::splat(0.0), this_pos.x);
+        this_pos.y = mask_y.select(Simd::splat(0.0), this_pos.y);
+
+        let mask_x = this_pos.x.simd_lt(Simd::splat(0.0));
+        let mask_y = this_pos.y.simd_lt(Simd::splat(0.0));
+        this_pos.x = mask_x.select(Simd::splat(screen_rect.x), this_pos.x);
+        this_pos.y = mask_y.select(Simd::splat(screen_rect.y), this_pos.y);
+
+        this_pos.x.copy_to_slice(&mut self.pos_x[start..end]);
+        this_pos.y.copy_to_slice(&mut self.pos_y[start..end]);
+        this_vel.x.copy_to_slice(&mut self.vel_x[start..end]);
+        this_vel.y.copy_to_slice(&mut self.vel_y[start..end]);
+        flush_diagnostics!();
+        flush_rule_timing!();
+        flush_distance_histogram!();
+    }
+
+    fn columns(&self) -> [&[f32]; 4] {
+        [&self.pos_x, &self.pos_y, &self.vel_x, &self.vel_y]
+    }
+
+    fn num_chunks(&self) -> usize {
+        self.pos_x.len() / N
+    }
+}
+
+struct BoidsDoubleBuffer<const N: usize, const UNROLL: usize> {
+    boids: [UnsafeCell<BoidsVec<N, UNROLL>>; 2],
+    current_idx: usize,
+}
+
+impl<const N: usize, const UNROLL: usize> BoidsDoubleBuffer<N, UNROLL> {
+    fn new(active_boids: &[Boid], huge_pages: bool) -> Self {
+        let len = active_boids.len();
+        BoidsDoubleBuffer {
+            boids: [
+                UnsafeCell::new(BoidsVec::new_from_scalar(active_boids, huge_pages)),
+                UnsafeCell::new(BoidsVec::new_with_length(len, huge_pages)),
+            ],
+            current_idx: 0,
+        }
+    }
+
+    fn get_current_boids(&self) -> &BoidsVec<N, UNROLL> {
+        unsafe { &*self.boids[self.current_idx].get() }
+    }
+
+    #[allow(clippy::mut_from_ref)]
+    fn get_next_boids(&self) -> &mut BoidsVec<N, UNROLL> {
+        unsafe { &mut *self.boids[self.current_idx ^ 1].get() }
+    }
+
+    fn swap(&mut self) {
+        self.current_idx ^= 1;
+    }
+}
+
+unsafe impl<const N: usize, const UNROLL: usize> Sync for BoidsDoubleBuffer<N, UNROLL> {}
+
+/// A double buffer of one chunk width and unroll factor, picked at startup by `Flock::new`.
+trait Kernels {
+    fn prefault(&mut self);
+    /// Positions and velocities of the current buffer, x and y each in their own column.
+    fn columns(&self) -> [&[f32]; 4];
+    fn step(&mut self, dt: f32, rect_max: Vec2, rounds_per_boid: u32);
+}
+
+impl<const N: usize, const UNROLL: usize> Kernels for BoidsDoubleBuffer<N, UNROLL> {
+    // Without `--huge-pages` the next buffer is calloc'd and would otherwise fault in during the
+    // first update. Writing it in the update's chunks from the pool puts each page's first touch on
+    // a thread that owns it; the current buffer was already written on the main thread by
+    // `new_from_scalar`, and huge page columns are zeroed when they are allocated.
+    fn prefault(&mut self) {
+        use rayon::prelude::*;
+
+        let next = self.boids[self.current_idx ^ 1].get_mut();
+        for column in [
+            &mut next.pos_x,
+            &mut next.pos_y,
+            &mut next.vel_x,
+            &mut next.vel_y,
+        ] {
+            column
+                .par_chunks_mut(N)
+                .with_min_len(8)
+                .for_each(|chunk| chunk.fill(0.0));
+        }
+    }
+
+    fn columns(&self) -> [&[f32]; 4] {
+        self.get_current_boids().columns()
+    }
+
+    fn step(&mut self, dt: f32, rect_max: Vec2, rounds_per_boid: u32) {
+        let rounds = rounds_per_boid * N as u32;
+        #[cfg(not(feature = "threaded"))]
+        alloc_free!("update_boids", {
+            let current_boids = self.get_current_boids();
+            let next_boids = self.get_next_boids();
+            for chunk_idx in 0..current_boids.num_chunks() {
+                next_boids.update(chunk_idx, dt, current_boids, rect_max);
+                extra_work(chunk_idx, rounds);
+            }
+        });
+        #[cfg(feature = "threaded")]
+        alloc_free!(pool "update_boids", {
+            let num_chunks = self.get_current_boids().num_chunks();
+            (0..num_chunks)
+                .into_par_iter()
+                .with_min_len(8)
+                .for_each(|chunk_idx| {
+                    tracy_scope!("update_boids_thread");
+                    self.get_next_boids().update(
+                        chunk_idx,
+                        dt,
+                        self.get_current_boids(),
+                        rect_max,
+                    );
+                    extra_work(chunk_idx, rounds);
+                });
+        });
+        self.swap();
+    }
+}
+
+/// Alignment reads a neighbor's position and velocity, cohesion and separation only its position,
+/// each from its own column.
+#[cfg(feature = "bandwidth")]
+pub const NEIGHBOR_BYTES: usize = (4 + 2 + 2) * std::mem::size_of::<f32>();
+#[cfg(feature = "bandwidth")]
+pub const WRITTEN_BYTES: usize = 4 * std::mem::size_of::<f32>();
+
+/// The flock behind the frame loop, double buffered so a step reads one set of columns and
+/// writes the other.
+pub struct Flock {
+    boids: Box<dyn Kernels>,
+    /// Hash rounds per boid, as `--extra-work` gives it.
+    extra_work: u32,
+}
+
+impl Flock {
+    /// Builds the kernels for `--simd-width` and `--unroll`, which the parser has already checked
+    /// against `SIMD_WIDTHS` and `UNROLLS`.
+    pub fn new(boids: &[Boid], extra_work: u32, options: &SimdOptions) -> Self {
+        let boids: Box<dyn Kernels> = match (options.width, options.unroll) {
+            (4, 1) => Box::new(BoidsDoubleBuffer::<4, 1>::new(boids, options.huge_pages)),
+            (4, 2) => Box::new(BoidsDoubleBuffer::<4, 2>::new(boids, options.huge_pages)),
+            (4, 4) => Box::new(BoidsDoubleBuffer::<4, 4>::new(boids, options.huge_pages)),
+            (8, 1) => Box::new(BoidsDoubleBuffer::<8, 1>::new(boids, options.huge_pages)),
+            (8, 2) => Box::new(BoidsDoubleBuffer::<8, 2>::new(boids, options.huge_pages)),
+            (8, 4) => Box::new(BoidsDoubleBuffer::<8, 4>::new(boids, options.huge_pages)),
+            (16, 1) => Box::new(BoidsDoubleBuffer::<16, 1>::new(boids, options.huge_pages)),
+            (16, 2) => Box::new(BoidsDoubleBuffer::<16, 2>::new(boids, options.huge_pages)),
+            (16, 4) => Box::new(BoidsDoubleBuffer::<16, 4>::new(boids, options.huge_pages)),
+            (width, unroll) => unreachable!("no kernels for f32x{width} unrolled {unroll} times"),
+        };
+        Flock { boids, extra_work }
+    }
+
+    pub fn prefault(&mut self) {
+        self.boids.prefault();
+    }
+
+    pub fn len(&self) -> usize {
+        self.boids.columns()[0].len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn boids(&self) -> impl Iterator<Item = Boid> + '_ {
+        let [pos_x, pos_y, vel_x, vel_y] = self.boids.columns();
+        pos_x.iter().zip(pos_y).zip(vel_x.iter().zip(vel_y)).map(
+            |((&pos_x, &pos_y), (&vel_x, &vel_y))| Boid {
+                position: Vec2::new(pos_x, pos_y),
+                velocity: Vec2::new(vel_x, vel_y),
+            },
+        )
+    }
+
+    pub fn step(&mut self, dt: f32, rect_max: Vec2) {
+        self.boids.step(dt, rect_max, self.extra_work);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::Rng;
+
+    use super::*;
+
+    fn lane<const N: usize>(v: SimdVec2<N>, lane: usize) -> Vec2 {
+        Vec2::new(v.x[lane], v.y[lane])
+    }
+
+    // The rules themselves are tested on the packed boids in boids-common; this checks that the
+    // columns give the same accelerations. Five chunks, so an unroll of 2 or 4 leaves one over
+    // after its full rounds.
+    fn check_kernels_against_scalar_rules<const N: usize, const UNROLL: usize>() {
+        let mut rng = seeded_rng(7);
+        let mut boids: Vec<Boid> = (0..5 * N)
+            .map(|_| {
+                let position = Vec2::new(rng.gen_range(0.0..150.0), rng.gen_range(0.0..150.0));
+                let velocity = Vec2::new(rng.gen_range(-50.0..50.0), rng.gen_range(-50.0..50.0));
+                Boid::new(position, velocity)
+            })
+            .collect();
+        // Coincident neighbors, one pair in the same chunk and one across chunks
+        boids[3].position = boids[1].position;
+        boids[N + 2].position = boids[5].position;
+        let simd_boids = BoidsVec::<N, UNROLL>::new_from_scalar(&boids, false);
+        for (idx, boid) in boids.iter().enumerate() {
+            #[cfg(not(feature = "horizontal"))]
+            let simd = lane(simd_boids.calc_acceleration(idx / N), idx % N);
+            #[cfg(feature = "horizontal")]
+            let simd = lane(simd_boids.calc_acceleration_horizontal(idx / N), idx % N);
+            let scalar = boid.calc_acceleration(idx, &boids, &[], 0.0);
+            assert!(
+                simd.distance(scalar) < 1e-2,
+                "f32x{N} unrolled {UNROLL} times, boid {idx}: SIMD {simd} against scalar {scalar}"
+            );
+        }
+    }
+
+    #[test]
+    fn kernels_match_the_scalar_rules_within_and_across_chunks() {
+        check_kernels_against_scalar_rules::<4, 1>();
+        check_kernels_against_scalar_rules::<8, 1>();
+        check_kernels_against_scalar_rules::<8, 2>();
+        check_kernels_against_scalar_rules::<16, 4>();
+    }
+}
@@ -1,2 +1,2 @@
-$ArgList = @("run", "--features=threaded", "--release", "--", "4000")
+$ArgList = @("+nightly", "run", "--features=threaded", "--release", "--", "4000")
 Start-Process cargo -NoNewWindow -PassThru -WorkingDirectory "$PSScriptRoot\..\boids-simd-rs" -ArgumentList $ArgList
\ No newline at end of file
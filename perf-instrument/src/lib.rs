@@ -0,0 +1,70 @@
+//! Profiling instrumentation shared by the boids crates. Without the `enable` feature every
+//! span, plot and frame mark compiles to nothing, so builds that aren't being profiled carry
+//! no instrumentation at all, not even the disabled-client checks.
+
+#[doc(hidden)]
+pub use tracy_client;
+
+/// Opens a span named `$name` that lasts until the end of the enclosing block.
+#[cfg(feature = "enable")]
+#[macro_export]
+macro_rules! tracy_scope {
+    ($name:literal) => {
+        let _tracy_span = $crate::tracy_client::span!($name);
+    };
+}
+
+#[cfg(not(feature = "enable"))]
+#[macro_export]
+macro_rules! tracy_scope {
+    ($name:literal) => {};
+}
+
+/// Records `$value` on the plot named `$name`.
+#[cfg(feature = "enable")]
+#[macro_export]
+macro_rules! tracy_plot {
+    ($name:literal, $value:expr) => {
+        $crate::tracy_client::plot!($name, ($value) as f64);
+    };
+}
+
+#[cfg(not(feature = "enable"))]
+#[macro_export]
+macro_rules! tracy_plot {
+    ($name:literal, $value:expr) => {
+        let _ = $value;
+    };
+}
+
+/// Puts a message on the timeline, formatted like `format!`. Disabled, the arguments are only
+/// borrowed and nothing is formatted.
+#[cfg(feature = "enable")]
+#[macro_export]
+macro_rules! tracy_message {
+    ($($arg:tt)*) => {{
+        if let Some(client) = $crate::tracy_client::Client::running() {
+            client.message(&format!($($arg)*), 0);
+        }
+    }};
+}
+
+#[cfg(not(feature = "enable"))]
+#[macro_export]
+macro_rules! tracy_message {
+    ($($arg:tt)*) => {{
+        let _ = format_args!($($arg)*);
+    }};
+}
+
+/// Starts the profiler client, call once at the top of `main`.
+pub fn start() {
+    #[cfg(feature = "enable")]
+    tracy_client::Client::start();
+}
+
+/// Marks the end of a frame.
+pub fn frame_mark() {
+    #[cfg(feature = "enable")]
+    tracy_client::frame_mark();
+}